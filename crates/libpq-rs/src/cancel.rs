@@ -0,0 +1,68 @@
+//! Wrap LibPQ's own `PGcancel` struct.
+
+use crate::debug::*;
+use crate::errors::*;
+use crate::ffi::*;
+use std::os::raw::c_char;
+
+/// Struct wrapping the LibPQ functions related to _canceling_ queries.
+///
+/// A [`PQCancel`] can be safely used from a thread other than the one using
+/// the connection it was created from.
+///
+/// See [Canceling Queries in Progress](https://www.postgresql.org/docs/current/libpq-cancel.html)
+///
+pub struct PQCancel {
+  cancel: *mut pq_sys::pg_cancel,
+}
+
+unsafe impl Send for PQCancel {}
+unsafe impl Sync for PQCancel {}
+
+debug_self!(PQCancel, cancel, "@");
+
+impl Drop for PQCancel {
+  /// Frees a data structure created by `PQgetCancel`.
+  ///
+  /// See [`PQfreeCancel`](https://www.postgresql.org/docs/current/libpq-cancel.html#LIBPQ-PQFREECANCEL)
+  ///
+  fn drop(&mut self) {
+    debug_drop!(self);
+    unsafe { pq_sys::PQfreeCancel(self.cancel) };
+  }
+}
+
+impl TryFrom<*mut pq_sys::pg_cancel> for PQCancel {
+  type Error = PQError;
+
+  /// Create a [`PQCancel`] from a LibPQ own `PGcancel` structure.
+  ///
+  fn try_from(cancel: *mut pq_sys::pg_cancel) -> PQResult<Self> {
+    match cancel.is_null() {
+      true => Err("Unable to create cancel object".into()),
+      _ => Ok(debug_create!(Self { cancel })),
+    }
+  }
+}
+
+impl PQCancel {
+  /// Requests that the server abandon processing of the current command.
+  ///
+  /// Successful dispatch is no guarantee that the request will have any
+  /// effect, as the command might have completed already.
+  ///
+  /// See [`PQcancel`](https://www.postgresql.org/docs/current/libpq-cancel.html#LIBPQ-PQCANCEL)
+  ///
+  pub fn pq_cancel(&self) -> PQResult<()> {
+    let mut buffer = [0 as c_char; 256];
+
+    unsafe {
+      match pq_sys::PQcancel(self.cancel, buffer.as_mut_ptr(), buffer.len() as i32) {
+        1 => Ok(()),
+        _ => Err(to_string_lossy(buffer.as_ptr())
+          .map(|message| format!("Unable to cancel query: {}", message))
+          .into()),
+      }
+    }
+  }
+}
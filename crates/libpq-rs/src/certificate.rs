@@ -0,0 +1,455 @@
+//! X.509 certificates presented by the server, read through OpenSSL.
+
+use crate::errors::*;
+use crate::ffi::*;
+use openssl_sys::*;
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::os::raw::c_char;
+use std::os::raw::c_int;
+use std::os::raw::c_uchar;
+use std::os::raw::c_uint;
+use std::os::raw::c_void;
+use std::ptr::null_mut;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/* ========================================================================== *
+ * NAMES                                                                      *
+ * ========================================================================== */
+
+/// A distinguished name (subject or issuer) of a [`PQCertificate`].
+///
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct PQDistinguishedName {
+  /// All attributes (short name and value) in certificate order.
+  pub entries: Vec<(String, String)>,
+}
+
+impl Display for PQDistinguishedName {
+  /// Format this name like RFC 4514 does (most specific attribute first),
+  /// without escaping.
+  ///
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let entries = self.entries
+      .iter()
+      .rev()
+      .map(|(key, value)| format!("{}={}", key, value))
+      .collect::<Vec<_>>();
+    f.write_str(&entries.join(","))
+  }
+}
+
+impl PQDistinguishedName {
+  /// Return the first value of the specified attribute (e.g. `O`), if any.
+  ///
+  pub fn get(&self, key: &str) -> Option<&str> {
+    self.entries
+      .iter()
+      .find(|(k, _)| k == key)
+      .map(|(_, value)| value.as_str())
+  }
+
+  /// Return the common name (`CN`), if any.
+  ///
+  pub fn common_name(&self) -> Option<&str> {
+    self.get("CN")
+  }
+
+  /// Read the attributes of an OpenSSL `X509_NAME`.
+  ///
+  unsafe fn from_raw(name: *mut X509_NAME) -> Self {
+    let mut entries = Vec::<(String, String)>::new();
+    if name.is_null() { return Self { entries } }
+
+    for index in 0 .. unsafe { X509_NAME_entry_count(name) } {
+      let entry = unsafe { X509_NAME_get_entry(name, index) };
+      if entry.is_null() { continue }
+
+      let nid = unsafe { OBJ_obj2nid(X509_NAME_ENTRY_get_object(entry)) };
+      let key = to_string_lossy(unsafe { OBJ_nid2sn(nid) }).unwrap_or_else(|| format!("NID{}", nid));
+      let value = unsafe { asn1_string_to_utf8(X509_NAME_ENTRY_get_data(entry)) };
+
+      if let Some(value) = value {
+        entries.push((key, value));
+      }
+    }
+
+    Self { entries }
+  }
+}
+
+/// A subject alternative name of a [`PQCertificate`].
+///
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub enum PQSubjectAltName {
+  /// A DNS name (`DNS:`).
+  Dns(String),
+  /// An IP address (`IP:`).
+  Ip(IpAddr),
+  /// An email address (`email:`).
+  Email(String),
+  /// A URI (`URI:`).
+  Uri(String),
+}
+
+impl Display for PQSubjectAltName {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Dns(name) => write!(f, "DNS:{}", name),
+      Self::Ip(address) => write!(f, "IP:{}", address),
+      Self::Email(email) => write!(f, "email:{}", email),
+      Self::Uri(uri) => write!(f, "URI:{}", uri),
+    }
+  }
+}
+
+/* ========================================================================== *
+ * CERTIFICATES                                                               *
+ * ========================================================================== */
+
+/// An X.509 certificate, as presented by the server during the TLS handshake.
+///
+/// See [`PQConnection::peer_certificates`][crate::connection::PQConnection::peer_certificates]
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PQCertificate {
+  /// The subject of the certificate.
+  pub subject: PQDistinguishedName,
+  /// The issuer of the certificate.
+  pub issuer: PQDistinguishedName,
+  /// The subject alternative names of the certificate.
+  pub subject_alt_names: Vec<PQSubjectAltName>,
+  /// The serial number of the certificate (upper case hex).
+  pub serial_number: String,
+  /// The start of the certificate's validity window.
+  pub not_before: SystemTime,
+  /// The end of the certificate's validity window.
+  pub not_after: SystemTime,
+  /// The SHA-256 digest of the DER encoded certificate.
+  pub sha256_fingerprint: [u8; 32],
+  /// The DER encoded certificate.
+  pub der: Vec<u8>,
+}
+
+impl PQCertificate {
+  /// Read an OpenSSL `X509` certificate.
+  ///
+  /// # Safety
+  ///
+  /// The pointer must be a valid `X509`, alive for the duration of the call.
+  ///
+  pub(crate) unsafe fn from_raw(x509: *mut X509) -> PQResult<Self> {
+    if x509.is_null() {
+      return Err("Unable to read certificate (null ptr)".into());
+    }
+
+    unsafe {
+      Ok(Self {
+        subject: PQDistinguishedName::from_raw(X509_get_subject_name(x509)),
+        issuer: PQDistinguishedName::from_raw(X509_get_issuer_name(x509)),
+        subject_alt_names: subject_alt_names(x509),
+        serial_number: serial_number(x509)?,
+        not_before: asn1_time_to_system_time(X509_getm_notBefore(x509))?,
+        not_after: asn1_time_to_system_time(X509_getm_notAfter(x509))?,
+        sha256_fingerprint: sha256_fingerprint(x509)?,
+        der: der(x509)?,
+      })
+    }
+  }
+
+  /// Return the SHA-256 fingerprint of this certificate as colon-separated,
+  /// upper case, hex (e.g. `AB:CD:...`).
+  ///
+  pub fn sha256_fingerprint_hex(&self) -> String {
+    self.sha256_fingerprint
+      .iter()
+      .map(|byte| format!("{:02X}", byte))
+      .collect::<Vec<_>>()
+      .join(":")
+  }
+
+  /// Check whether this certificate has the specified SHA-256 fingerprint,
+  /// in hex (ignoring case and colons), for certificate pinning.
+  ///
+  pub fn matches_fingerprint(&self, fingerprint: &str) -> bool {
+    let expected = fingerprint.replace(':', "").to_ascii_uppercase();
+    expected == self.sha256_fingerprint_hex().replace(':', "")
+  }
+
+  /// Check whether the specified time falls within this certificate's
+  /// validity window.
+  ///
+  pub fn is_valid_at(&self, time: SystemTime) -> bool {
+    time >= self.not_before && time <= self.not_after
+  }
+
+  /// Return how long until this certificate expires, or `None` if it
+  /// already has.
+  ///
+  pub fn expires_in(&self) -> Option<Duration> {
+    self.not_after.duration_since(SystemTime::now()).ok()
+  }
+
+  /// Check whether this certificate expires (or has expired) within the
+  /// specified duration, for expiry monitoring.
+  ///
+  pub fn expires_within(&self, duration: Duration) -> bool {
+    self.expires_in().is_none_or(|remaining| remaining <= duration)
+  }
+}
+
+/// Read the certificate chain presented by the peer of an OpenSSL `SSL`
+/// connection, starting with the peer's own certificate.
+///
+/// # Safety
+///
+/// The pointer must be a valid `SSL`, alive for the duration of the call.
+///
+pub(crate) unsafe fn peer_certificate_chain(ssl: *mut SSL) -> PQResult<Vec<PQCertificate>> {
+  // On the client side the chain includes the peer's certificate, and its
+  // reference count is not incremented (we must not free it)
+  let chain = unsafe { SSL_get_peer_cert_chain(ssl) } as *mut OPENSSL_STACK;
+  if chain.is_null() { return Ok(Vec::new()) }
+
+  (0 .. unsafe { OPENSSL_sk_num(chain) })
+    .map(|index| unsafe { PQCertificate::from_raw(OPENSSL_sk_value(chain, index) as *mut X509) })
+    .collect()
+}
+
+/* ========================================================================== *
+ * INTERNALS                                                                  *
+ * ========================================================================== */
+
+/// Convert an `ASN1_STRING` into a UTF-8 [`String`].
+///
+unsafe fn asn1_string_to_utf8(string: *const ASN1_STRING) -> Option<String> {
+  if string.is_null() { return None }
+
+  let mut buffer: *mut c_uchar = null_mut();
+  let length = unsafe { ASN1_STRING_to_UTF8(&mut buffer, string) };
+  if length < 0 || buffer.is_null() { return None }
+
+  let bytes = unsafe { std::slice::from_raw_parts(buffer, length as usize) };
+  let result = String::from_utf8_lossy(bytes).to_string();
+  unsafe { OPENSSL_free(buffer as *mut c_void) };
+  Some(result)
+}
+
+/// Copy the raw bytes of an `ASN1_STRING`.
+///
+unsafe fn asn1_string_bytes(string: *const ASN1_STRING) -> Vec<u8> {
+  if string.is_null() { return Vec::new() }
+
+  let data = unsafe { ASN1_STRING_get0_data(string) };
+  let length = unsafe { ASN1_STRING_length(string) };
+  match data.is_null() || length < 0 {
+    true => Vec::new(),
+    false => unsafe { std::slice::from_raw_parts(data, length as usize) }.to_vec(),
+  }
+}
+
+/// Free a `GENERAL_NAME` (as an element of a stack).
+///
+unsafe extern "C" fn free_general_name(name: *mut c_void) {
+  unsafe { GENERAL_NAME_free(name as *mut GENERAL_NAME) }
+}
+
+/// Read the subject alternative names of a certificate, ignoring the kinds
+/// we don't understand.
+///
+unsafe fn subject_alt_names(x509: *mut X509) -> Vec<PQSubjectAltName> {
+  let names = unsafe { X509_get_ext_d2i(x509, NID_subject_alt_name, null_mut(), null_mut()) } as *mut OPENSSL_STACK;
+  if names.is_null() { return Vec::new() }
+
+  let mut result = Vec::<PQSubjectAltName>::new();
+
+  for index in 0 .. unsafe { OPENSSL_sk_num(names) } {
+    let name = unsafe { &*(OPENSSL_sk_value(names, index) as *const GENERAL_NAME) };
+    let bytes = unsafe { asn1_string_bytes(name.d as *const ASN1_STRING) };
+    let string = || String::from_utf8_lossy(&bytes).to_string();
+
+    let name = match name.type_ {
+      GEN_DNS => Some(PQSubjectAltName::Dns(string())),
+      GEN_EMAIL => Some(PQSubjectAltName::Email(string())),
+      GEN_URI => Some(PQSubjectAltName::Uri(string())),
+      GEN_IPADD => match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes.as_slice()).ok().map(|ip| PQSubjectAltName::Ip(IpAddr::from(ip))),
+        16 => <[u8; 16]>::try_from(bytes.as_slice()).ok().map(|ip| PQSubjectAltName::Ip(IpAddr::from(ip))),
+        _ => None,
+      },
+      _ => None,
+    };
+
+    result.extend(name);
+  }
+
+  unsafe { OPENSSL_sk_pop_free(names, Some(free_general_name)) };
+  result
+}
+
+/// Read the serial number of a certificate, as upper case hex.
+///
+unsafe fn serial_number(x509: *mut X509) -> PQResult<String> {
+  let bignum = unsafe { ASN1_INTEGER_to_BN(X509_get_serialNumber(x509), null_mut()) };
+  if bignum.is_null() {
+    return Err("Unable to read certificate serial number".into());
+  }
+
+  let hex = unsafe { BN_bn2hex(bignum) };
+  unsafe { BN_free(bignum) };
+  if hex.is_null() {
+    return Err("Unable to read certificate serial number".into());
+  }
+
+  let result = to_string(hex as *const c_char);
+  unsafe { OPENSSL_free(hex as *mut c_void) };
+  result
+}
+
+/// Convert an `ASN1_TIME` into a [`SystemTime`].
+///
+unsafe fn asn1_time_to_system_time(time: *const ASN1_TIME) -> PQResult<SystemTime> {
+  let epoch = unsafe { ASN1_TIME_set(null_mut(), 0) };
+  if epoch.is_null() || time.is_null() {
+    unsafe { ASN1_TIME_free(epoch) };
+    return Err("Unable to read certificate validity".into());
+  }
+
+  let (mut days, mut seconds): (c_int, c_int) = (0, 0);
+  let result = unsafe { ASN1_TIME_diff(&mut days, &mut seconds, epoch, time) };
+  unsafe { ASN1_TIME_free(epoch) };
+
+  if result != 1 {
+    return Err("Unable to read certificate validity".into());
+  }
+
+  // Days and seconds always have the same sign
+  let offset = (days as i64) * 86400 + (seconds as i64);
+  match offset >= 0 {
+    true => Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(offset as u64)),
+    false => Ok(SystemTime::UNIX_EPOCH - Duration::from_secs(offset.unsigned_abs())),
+  }
+}
+
+/// Compute the SHA-256 fingerprint of a certificate.
+///
+unsafe fn sha256_fingerprint(x509: *mut X509) -> PQResult<[u8; 32]> {
+  let mut digest = [0u8; 32];
+  let mut length: c_uint = 0;
+
+  match unsafe { X509_digest(x509, EVP_sha256(), digest.as_mut_ptr(), &mut length) } {
+    1 if length == 32 => Ok(digest),
+    _ => Err("Unable to compute certificate fingerprint".into()),
+  }
+}
+
+/// Encode a certificate in DER format.
+///
+unsafe fn der(x509: *mut X509) -> PQResult<Vec<u8>> {
+  let length = unsafe { i2d_X509(x509, null_mut()) };
+  if length <= 0 {
+    return Err("Unable to encode certificate".into());
+  }
+
+  let mut buffer = vec![0u8; length as usize];
+  let mut pointer = buffer.as_mut_ptr();
+
+  match unsafe { i2d_X509(x509, &mut pointer) } {
+    written if written == length => Ok(buffer),
+    _ => Err("Unable to encode certificate".into()),
+  }
+}
+
+/* ========================================================================== *
+ * TESTS                                                                      *
+ * ========================================================================== */
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::net::Ipv4Addr;
+  use std::net::Ipv6Addr;
+
+  /// A self-signed (P-256) certificate, valid from 2024-01-01 to 2034-01-01.
+  static CERTIFICATE: &str = "\
+-----BEGIN CERTIFICATE-----
+MIICFDCCAbmgAwIBAgIDGis8MAoGCCqGSM49BAMCMDsxCzAJBgNVBAYTAlVTMREw
+DwYDVQQKDAhUZXN0IE9yZzEZMBcGA1UEAwwQdGVzdC5leGFtcGxlLmNvbTAeFw0y
+NDAxMDEwMDAwMDBaFw0zNDAxMDEwMDAwMDBaMDsxCzAJBgNVBAYTAlVTMREwDwYD
+VQQKDAhUZXN0IE9yZzEZMBcGA1UEAwwQdGVzdC5leGFtcGxlLmNvbTBZMBMGByqG
+SM49AgEGCCqGSM49AwEHA0IABFC4fGSgQA3hKgeXxswFwyOiqOTawkCTaZmsgAlq
+2+9r5/5unJANPukrIb93yvnHFZ+ycldG8RynSKo7OO95m9ejgaswgagwHQYDVR0O
+BBYEFLoArtPy3hg3tc0IIw0Vu5Vm8iiKMB8GA1UdIwQYMBaAFLoArtPy3hg3tc0I
+Iw0Vu5Vm8iiKMA8GA1UdEwEB/wQFMAMBAf8wVQYDVR0RBE4wTIIQdGVzdC5leGFt
+cGxlLmNvbYINKi5leGFtcGxlLm9yZ4cEfwAAAYcQAAAAAAAAAAAAAAAAAAAAAYER
+YWRtaW5AZXhhbXBsZS5jb20wCgYIKoZIzj0EAwIDSQAwRgIhAKWPTgmHMLMm3AOE
+z/uofcNZetWGa6d5NecHlDyKteANAiEAuTw3FMI5GKhIRcsBJtPgZOlFrivwzD2y
+m+15WD16PZM=
+-----END CERTIFICATE-----
+";
+
+  fn certificate() -> PQCertificate {
+    unsafe {
+      let bio = BIO_new_mem_buf(CERTIFICATE.as_ptr() as *const c_void, CERTIFICATE.len() as c_int);
+      assert!(!bio.is_null());
+      let x509 = PEM_read_bio_X509(bio, null_mut(), None, null_mut());
+      BIO_free_all(bio);
+
+      let certificate = PQCertificate::from_raw(x509);
+      X509_free(x509);
+      certificate.unwrap()
+    }
+  }
+
+  #[test]
+  fn certificate_from_raw() {
+    let certificate = certificate();
+
+    let name = vec![
+      ("C".to_string(), "US".to_string()),
+      ("O".to_string(), "Test Org".to_string()),
+      ("CN".to_string(), "test.example.com".to_string()),
+    ];
+    assert_eq!(certificate.subject.entries, name);
+    assert_eq!(certificate.issuer.entries, name);
+    assert_eq!(certificate.subject.to_string(), "CN=test.example.com,O=Test Org,C=US");
+    assert_eq!(certificate.subject.common_name(), Some("test.example.com"));
+    assert_eq!(certificate.subject.get("O"), Some("Test Org"));
+    assert_eq!(certificate.subject.get("OU"), None);
+
+    assert_eq!(certificate.subject_alt_names, vec![
+      PQSubjectAltName::Dns("test.example.com".to_string()),
+      PQSubjectAltName::Dns("*.example.org".to_string()),
+      PQSubjectAltName::Ip(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+      PQSubjectAltName::Ip(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+      PQSubjectAltName::Email("admin@example.com".to_string()),
+    ]);
+
+    assert_eq!(certificate.serial_number, "1A2B3C");
+    assert_eq!(certificate.not_before, SystemTime::UNIX_EPOCH + Duration::from_secs(1704067200));
+    assert_eq!(certificate.not_after, SystemTime::UNIX_EPOCH + Duration::from_secs(2019686400));
+    assert_eq!(certificate.der.len(), 536);
+  }
+
+  #[test]
+  fn certificate_fingerprint() {
+    let certificate = certificate();
+    let fingerprint = "6E:65:AE:42:B7:73:F8:48:AC:2E:72:48:4F:DB:C1:AF:1E:CE:B3:81:50:87:5C:86:3A:DF:9D:26:E6:21:D5:F4";
+
+    assert_eq!(certificate.sha256_fingerprint_hex(), fingerprint);
+    assert!(certificate.matches_fingerprint(fingerprint));
+    assert!(certificate.matches_fingerprint(&fingerprint.replace(':', "").to_ascii_lowercase()));
+    assert!(!certificate.matches_fingerprint(&fingerprint.replace("6E", "6F")));
+  }
+
+  #[test]
+  fn certificate_validity() {
+    let certificate = certificate();
+
+    assert!(certificate.is_valid_at(certificate.not_before));
+    assert!(certificate.is_valid_at(certificate.not_after));
+    assert!(!certificate.is_valid_at(certificate.not_before - Duration::from_secs(1)));
+    assert!(!certificate.is_valid_at(certificate.not_after + Duration::from_secs(1)));
+  }
+}
@@ -0,0 +1,412 @@
+//! Layered connection configuration, explaining where each value came from.
+
+use crate::conninfo::secret_predicate;
+use crate::conninfo::PQConninfo;
+use crate::errors::*;
+use std::fmt::Debug;
+use std::fmt::Display;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// What to display in place of secret values.
+static REDACTED: &str = "********";
+
+/* ========================================================================== *
+ * SOURCES                                                                    *
+ * ========================================================================== */
+
+/// A source of connection option values, layered by a [`PQConfigBuilder`].
+///
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub enum PQConfigSource {
+  /// LibPQ's own compiled-in defaults.
+  Defaults,
+  /// A `PG*` environment variable (with its name).
+  Environment(String),
+  /// A named service from a connection service file (with its name and file).
+  Service(String, PathBuf),
+  /// A connection string (DSN or URI).
+  Dsn,
+  /// A value set programmatically.
+  Override,
+}
+
+impl Display for PQConfigSource {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Defaults => write!(f, "libpq defaults"),
+      Self::Environment(name) => write!(f, "environment variable {}", name),
+      Self::Service(name, file) => write!(f, "service \"{}\" in {}", name, file.display()),
+      Self::Dsn => write!(f, "connection string"),
+      Self::Override => write!(f, "override"),
+    }
+  }
+}
+
+/// A connection option resolved by a [`PQConfigBuilder`], and its provenance.
+///
+#[derive(Clone, PartialEq, Eq)]
+pub struct PQConfigEntry {
+  /// The keyword of the option.
+  pub keyword: String,
+  /// The value of the option.
+  pub value: String,
+  /// The source that supplied the value.
+  pub source: PQConfigSource,
+  /// Earlier sources whose values were overridden, in layering order.
+  pub overridden: Vec<(PQConfigSource, String)>,
+}
+
+impl Debug for PQConfigEntry {
+  /// Format this [`PQConfigEntry`] with its values redacted, if secret.
+  ///
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let is_secret = secret_predicate()(&self.keyword);
+    let redact = |value: &str| match is_secret {
+      true => REDACTED.to_string(),
+      false => value.to_string(),
+    };
+
+    let overridden = self.overridden
+      .iter()
+      .map(|(source, value)| (source, redact(value)))
+      .collect::<Vec<_>>();
+
+    f.debug_struct("PQConfigEntry")
+      .field("keyword", &self.keyword)
+      .field("value", &redact(&self.value))
+      .field("source", &self.source)
+      .field("overridden", &overridden)
+      .finish()
+  }
+}
+
+/* ========================================================================== *
+ * BUILDER                                                                    *
+ * ========================================================================== */
+
+/// A builder layering connection options from multiple sources, where each
+/// layer overrides the values of the ones added before it.
+///
+/// Layers are only applied as explicitly added: a typical configuration
+/// layers [defaults][PQConfigBuilder::with_libpq_defaults], the
+/// [environment][PQConfigBuilder::with_environment], a
+/// [service][PQConfigBuilder::with_service], a [DSN][PQConfigBuilder::with_dsn]
+/// and [overrides][PQConfigBuilder::with_override] in this order.
+///
+/// See [Environment Variables](https://www.postgresql.org/docs/current/libpq-envars.html)
+/// See [The Connection Service File](https://www.postgresql.org/docs/current/libpq-pgservice.html)
+///
+#[derive(Clone, Default)]
+pub struct PQConfigBuilder {
+  entries: Vec<PQConfigEntry>,
+}
+
+impl Debug for PQConfigBuilder {
+  /// Format this [`PQConfigBuilder`] as its (redacted) provenance report.
+  ///
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("PQConfigBuilder")
+      .field("entries", &self.provenance().lines().collect::<Vec<_>>())
+      .finish()
+  }
+}
+
+impl PQConfigBuilder {
+  /// Layer LibPQ's compiled-in defaults.
+  ///
+  /// Unlike [`PQConninfo::from_libpq_defaults`], this does _not_ include any
+  /// value from the environment, which can be layered separately.
+  ///
+  /// See [`PQconndefaults`](https://www.postgresql.org/docs/current/libpq-connect.html#LIBPQ-PQCONNDEFAULTS)
+  ///
+  pub fn with_libpq_defaults(mut self) -> PQResult<Self> {
+    for descriptor in PQConninfo::descriptors()? {
+      if let Some(compiled) = descriptor.compiled {
+        self.layer(&descriptor.keyword, &compiled, PQConfigSource::Defaults);
+      }
+    }
+    Ok(self)
+  }
+
+  /// Layer the values of all `PG*` environment variables currently set.
+  ///
+  /// See [Environment Variables](https://www.postgresql.org/docs/current/libpq-envars.html)
+  ///
+  pub fn with_environment(mut self) -> PQResult<Self> {
+    for descriptor in PQConninfo::descriptors()? {
+      let envvar = match descriptor.envvar {
+        Some(envvar) => envvar,
+        None => continue,
+      };
+
+      if let Ok(value) = std::env::var(&envvar) {
+        self.layer(&descriptor.keyword, &value, PQConfigSource::Environment(envvar));
+      }
+    }
+    Ok(self)
+  }
+
+  /// Layer the values of a named service, looked up like LibPQ does in the
+  /// user's service file (`PGSERVICEFILE` or `~/.pg_service.conf`) first, and
+  /// in the system-wide one (`$PGSYSCONFDIR/pg_service.conf`) then.
+  ///
+  /// Unlike LibPQ, when `PGSYSCONFDIR` is not set the system-wide file is
+  /// not looked up at all, as LibPQ's compiled-in configuration directory
+  /// is not exposed by its API. The home directory is also taken from `HOME`,
+  /// rather than from the password database.
+  ///
+  /// See [The Connection Service File](https://www.postgresql.org/docs/current/libpq-pgservice.html)
+  ///
+  pub fn with_service(self, name: &str) -> PQResult<Self> {
+    let user = std::env::var_os("PGSERVICEFILE")
+      .map(PathBuf::from)
+      .or_else(|| home_file(".pg_service.conf"));
+    let system = std::env::var_os("PGSYSCONFDIR")
+      .map(|directory| PathBuf::from(directory).join("pg_service.conf"));
+
+    for file in [ user, system ].into_iter().flatten() {
+      if file.exists() && parse_service_file(&file, name)?.is_some() {
+        return self.with_service_file(&file, name);
+      }
+    }
+
+    Err(format!("Service \"{}\" not found", name).into())
+  }
+
+  /// Layer the values of a named service from the specified service file.
+  ///
+  /// See [The Connection Service File](https://www.postgresql.org/docs/current/libpq-pgservice.html)
+  ///
+  pub fn with_service_file(mut self, file: &Path, name: &str) -> PQResult<Self> {
+    let values = parse_service_file(file, name)?
+      .ok_or_else(|| format!("Service \"{}\" not found in {}", name, file.display()))?;
+
+    for (key, value) in values {
+      self.layer(&key, &value, PQConfigSource::Service(name.to_string(), file.to_path_buf()));
+    }
+    Ok(self)
+  }
+
+  /// Layer the values of a connection string (DSN or URI).
+  ///
+  /// See [`PQconninfoParse`](https://www.postgresql.org/docs/current/libpq-connect.html#LIBPQ-PQCONNINFOPARSE)
+  ///
+  pub fn with_dsn(mut self, dsn: &str) -> PQResult<Self> {
+    for (key, value) in PQConninfo::try_from(dsn)?.iter() {
+      self.layer(key, value, PQConfigSource::Dsn);
+    }
+    Ok(self)
+  }
+
+  /// Layer a single programmatic override.
+  ///
+  pub fn with_override(mut self, key: &str, value: &str) -> Self {
+    self.layer(key, value, PQConfigSource::Override);
+    self
+  }
+
+  /// Layer all values of a [`PQConninfo`] as programmatic overrides.
+  ///
+  pub fn with_overrides(mut self, info: &PQConninfo) -> Self {
+    for (key, value) in info.iter() {
+      self.layer(key, value, PQConfigSource::Override);
+    }
+    self
+  }
+
+  /// Return the resolved entry for the specified keyword, if any.
+  ///
+  pub fn entry(&self, key: &str) -> Option<&PQConfigEntry> {
+    self.entries.iter().find(|entry| entry.keyword == key)
+  }
+
+  /// Return all resolved entries, in the order they were first layered.
+  ///
+  pub fn entries(&self) -> &[PQConfigEntry] {
+    &self.entries
+  }
+
+  /// Return a report explaining which layer supplied each value, with all
+  /// secrets (such as passwords) redacted.
+  ///
+  pub fn provenance(&self) -> String {
+    let is_secret = secret_predicate();
+    let redact = |key: &str, value: &str| match is_secret(key) {
+      true => REDACTED.to_string(),
+      false => format!("{:?}", value),
+    };
+
+    self.entries.iter().map(|entry| {
+      let mut line = format!("{} = {} (from {})",
+        entry.keyword,
+        redact(&entry.keyword, &entry.value),
+        entry.source);
+
+      for (source, value) in entry.overridden.iter().rev() {
+        line.push_str(&format!(", overriding {} from {}", redact(&entry.keyword, value), source));
+      }
+
+      line
+    }).collect::<Vec<_>>().join("\n")
+  }
+
+  /// Build a [`PQConninfo`] from all resolved values.
+  ///
+  pub fn build(&self) -> PQConninfo {
+    self.entries
+      .iter()
+      .map(|entry| (entry.keyword.clone(), entry.value.clone()))
+      .collect()
+  }
+
+  /// Layer a single value, recording what it overrides.
+  ///
+  fn layer(&mut self, key: &str, value: &str, source: PQConfigSource) {
+    match self.entries.iter_mut().find(|entry| entry.keyword == key) {
+      Some(entry) => {
+        let value = std::mem::replace(&mut entry.value, value.to_string());
+        let source = std::mem::replace(&mut entry.source, source);
+        entry.overridden.push((source, value));
+      },
+      None => self.entries.push(PQConfigEntry {
+        keyword: key.to_string(),
+        value: value.to_string(),
+        source,
+        overridden: Vec::new(),
+      }),
+    }
+  }
+}
+
+/* ========================================================================== *
+ * FILES                                                                      *
+ * ========================================================================== */
+
+/// Return the path of a file in the user's home directory (from `HOME`).
+///
+pub(crate) fn home_file(name: &str) -> Option<PathBuf> {
+  std::env::var_os("HOME").map(|home| PathBuf::from(home).join(name))
+}
+
+/// Parse a connection service file, returning the values of the named
+/// service (if found).
+///
+/// The file is made of `[service]` sections, each followed by `key=value`
+/// lines. Blank lines, and lines starting with `#`, are ignored.
+///
+/// Like LibPQ, the named service is rejected when it uses unknown keywords
+/// or nests another service. Unlike LibPQ, whitespace around keywords and
+/// values is ignored.
+///
+fn parse_service_file(file: &Path, name: &str) -> PQResult<Option<Vec<(String, String)>>> {
+  let contents = std::fs::read_to_string(file)
+    .map_err(| err | format!("Error reading service file {}: {}", file.display(), err))?;
+
+  parse_service(&contents, name, &PQConninfo::known_keywords()?)
+    .map_err(|message| format!("{} in service file {}", message, file.display()).into())
+}
+
+/// Parse the contents of a connection service file, returning the values of
+/// the named service (if found), or an error message.
+///
+fn parse_service(contents: &str, name: &str, known: &[String]) -> Result<Option<Vec<(String, String)>>, String> {
+  let mut values: Option<Vec<(String, String)>> = None;
+
+  for (number, line) in contents.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') { continue }
+
+    if let Some(section) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+      // Sections after the one we found end it
+      if values.is_some() { break }
+      if section == name { values = Some(Vec::new()) }
+      continue;
+    }
+
+    let values = match values.as_mut() {
+      Some(values) => values,
+      None => continue,
+    };
+
+    let (key, value) = match line.split_once('=') {
+      Some((key, value)) => (key.trim(), value.trim()),
+      None => return Err(format!("Syntax error on line {}", number + 1)),
+    };
+
+    if key == "service" {
+      return Err(format!("Nested service specification on line {}", number + 1));
+    } else if ! known.iter().any(|keyword| keyword == key) {
+      return Err(format!("Unknown connection option \"{}\" on line {}", key, number + 1));
+    }
+
+    values.push((key.to_string(), value.to_string()));
+  }
+
+  Ok(values)
+}
+
+/* ========================================================================== *
+ * TESTS                                                                      *
+ * ========================================================================== */
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  static SERVICES: &str = "
+# A comment
+[first]
+host=one.example.com
+port = 5433
+
+[second]
+host=two.example.com
+dbname=db=with=equals
+";
+
+  fn known() -> Vec<String> {
+    [ "host", "port", "dbname", "service" ].iter().map(|key| key.to_string()).collect()
+  }
+
+  #[test]
+  fn service_sections() {
+    let first = parse_service(SERVICES, "first", &known()).unwrap().unwrap();
+    assert_eq!(first, [
+      ("host".to_string(), "one.example.com".to_string()),
+      ("port".to_string(), "5433".to_string()),
+    ]);
+
+    let second = parse_service(SERVICES, "second", &known()).unwrap().unwrap();
+    assert_eq!(second[1], ("dbname".to_string(), "db=with=equals".to_string()));
+
+    assert_eq!(parse_service(SERVICES, "third", &known()).unwrap(), None);
+  }
+
+  #[test]
+  fn service_errors() {
+    let error = parse_service("[svc]\nhost\n", "svc", &known()).unwrap_err();
+    assert_eq!(error, "Syntax error on line 2");
+
+    let error = parse_service("[svc]\nservice=other\n", "svc", &known()).unwrap_err();
+    assert_eq!(error, "Nested service specification on line 2");
+
+    let error = parse_service("[svc]\nhots=typo\n", "svc", &known()).unwrap_err();
+    assert_eq!(error, "Unknown connection option \"hots\" on line 2");
+
+    // Only the named service is validated
+    assert!(parse_service("[other]\nhots=typo\n[svc]\nhost=h\n", "svc", &known()).is_ok());
+  }
+
+  #[test]
+  fn redacted_entries() {
+    let builder = PQConfigBuilder::default()
+      .with_override("password", "first secret")
+      .with_override("password", "second secret")
+      .with_override("user", "joe");
+
+    let debug = format!("{:?}", builder.entries());
+    assert!(! debug.contains("secret"), "{}", debug);
+    assert!(debug.contains("joe"), "{}", debug);
+  }
+}
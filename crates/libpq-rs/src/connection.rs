@@ -1,22 +1,53 @@
 //! Wrap LibPQ's own `PGconn` struct.
 
+use crate::cancel::PQCancel;
+use crate::certificate::peer_certificate_chain;
+use crate::certificate::PQCertificate;
 use crate::conninfo::PQConninfo;
+use crate::credentials::PQCredentialProvider;
+use crate::cursor::PQCursor;
+use crate::cursor::PQCursorOptions;
 use crate::debug::*;
 use crate::errors::*;
+use crate::escape::PQIdentifier;
 use crate::ffi::*;
+use crate::futures::PQQueryFuture;
+use crate::futures::PQResponseStream;
+use crate::introspection::PQConnectionInfo;
+use crate::large_object::PQLargeObjectMode;
 use crate::notices::*;
 use crate::notifications::PQNotification;
+use crate::password::PQPasswordAlgorithm;
 use crate::response::PQResponse;
+use crate::response::PQRow;
+use crate::retry::PQRetryOptions;
+use crate::retry::PQRetryOutcome;
+use crate::rows::PQRowStream;
+use crate::security::PQSecurityPolicy;
+use crate::session::PQSessionReset;
+use crate::session::PQSessionTracker;
+use crate::session::REPORTED_PARAMETERS;
+use crate::transaction::PQTransaction;
+use crate::transaction::PQTransactionOptions;
+use crate::twophase::PQGlobalTransactionId;
+use crate::twophase::PQPreparedDecision;
+use crate::twophase::PQPreparedTransaction;
 use polling::Event;
 use polling::Events;
 use polling::Poller;
 use std::fmt::Debug;
 use std::os::fd::BorrowedFd;
+use std::os::raw::c_char;
 use std::os::raw::c_void;
+use std::path::Path;
 use std::ptr::null_mut;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::time::Duration;
+use std::time::Instant;
 
 /// Key for our `client_encoding` which must be always `UTF8`
 static ENCODING_KEY: &str = "client_encoding";
@@ -133,6 +164,19 @@ pub enum PQPollingInterest {
   Readable = 1,
 }
 
+/// The outcome of [`PQConnection::try_get_result`].
+///
+#[derive(Debug)]
+pub enum PQNextResult {
+  /// The next result of the command is available.
+  Ready(PQResponse),
+  /// All results of the command were returned.
+  Done,
+  /// The connection must become ready for the specified interest before
+  /// any more progress can be made.
+  Wait(PQPollingInterest),
+}
+
 /* ========================================================================== *
  * CONNECTION                                                                 *
  * ========================================================================== */
@@ -140,8 +184,13 @@ pub enum PQPollingInterest {
 /// Struct wrapping the LibPQ functions related to a _connection_.
 ///
 pub struct PQConnection {
-  connection: *mut pq_sys::pg_conn,
+  connection: AtomicPtr<pq_sys::pg_conn>,
   notice_processor: AtomicPtr<PQNoticeProcessorWrapper>,
+  session: PQSessionTracker,
+  result_size_limit: AtomicUsize,
+  credentials: Option<(PQConninfo, Arc<dyn PQCredentialProvider>)>,
+  policy: Option<PQSecurityPolicy>,
+  policy_violated: AtomicBool,
 }
 
 // ===== TRAITS ================================================================
@@ -155,7 +204,7 @@ impl Drop for PQConnection {
   ///
   fn drop(&mut self) {
     debug_drop!(self);
-    unsafe { pq_sys::PQfinish(self.connection) };
+    unsafe { pq_sys::PQfinish(self.as_ptr()) };
   }
 }
 
@@ -228,7 +277,15 @@ impl TryFrom<*mut pq_sys::pg_conn> for PQConnection {
 
     let connection = match conn.is_null() {
       true => Err("Unable to create connection (null ptr)"),
-      _ => Ok(PQConnection { connection: conn, notice_processor })
+      _ => Ok(PQConnection {
+        connection: AtomicPtr::new(conn),
+        notice_processor,
+        session: PQSessionTracker::new(),
+        result_size_limit: AtomicUsize::new(usize::MAX),
+        credentials: None,
+        policy: None,
+        policy_violated: AtomicBool::new(false),
+      })
     }?;
 
     let connection = debug_create!(connection);
@@ -237,7 +294,10 @@ impl TryFrom<*mut pq_sys::pg_conn> for PQConnection {
     connection.pq_set_notice_processor(Box::new(notice_processor));
 
     match connection.pq_status() {
-      PQConnectionStatus::Ok => Ok(connection),
+      PQConnectionStatus::Ok => {
+        connection.session.reset(connection.parameter_statuses());
+        Ok(connection)
+      },
       _ => Err(PQError::from(&connection)),
     }
   }
@@ -261,12 +321,164 @@ impl PQConnection {
     PQConnection::try_from(PQConninfo::default())
   }
 
+  /// Makes a new connection to the database server, with credentials obtained
+  /// from the specified provider right before connecting.
+  ///
+  /// The provider is invoked again each time the connection is re-established
+  /// by [`PQConnection::pq_reset`].
+  ///
+  /// See [`PQconnectdbParams`](https://www.postgresql.org/docs/current/libpq-connect.html#LIBPQ-PQCONNECTDBPARAMS)
+  ///
+  pub fn connect_with_credentials(info: PQConninfo, provider: Arc<dyn PQCredentialProvider>) -> PQResult<Self> {
+    let mut connection = Self::connect_with_provider(&info, provider.as_ref())?;
+    connection.credentials = Some((info, provider));
+    Ok(connection)
+  }
+
+  /// Makes a new connection to the database server, enforcing the specified
+  /// security policy now and every time the connection is re-established.
+  ///
+  /// The policy is [applied][PQSecurityPolicy::apply] to the connection
+  /// options before connecting, and [checked][PQSecurityPolicy::check]
+  /// against the established connection.
+  ///
+  pub fn connect_with_policy(info: PQConninfo, policy: PQSecurityPolicy) -> PQResult<Self> {
+    PQConnection::try_from(policy.apply(&info))?.with_security_policy(policy)
+  }
+
+  /// Makes a new connection to the database server, with credentials obtained
+  /// from the specified provider and enforcing the specified security policy.
+  ///
+  /// The policy is [applied][PQSecurityPolicy::apply] to the connection
+  /// options before the provider is first invoked, so that credentials are
+  /// never sent over a connection the policy would not allow.
+  ///
+  /// See [`PQConnection::connect_with_credentials`]
+  /// See [`PQConnection::connect_with_policy`]
+  ///
+  pub fn connect_with_credentials_and_policy(
+    info: PQConninfo,
+    provider: Arc<dyn PQCredentialProvider>,
+    policy: PQSecurityPolicy,
+  ) -> PQResult<Self> {
+    Self::connect_with_credentials(policy.apply(&info), provider)?.with_security_policy(policy)
+  }
+
+  /// Enforce the specified security policy on this connection, now and every
+  /// time it is re-established, failing (and closing it) if violated.
+  ///
+  /// Connections made with credentials from a provider should rather use
+  /// [`PQConnection::connect_with_credentials_and_policy`], as the policy
+  /// is then applied before the first credentials are ever sent.
+  ///
+  pub fn with_security_policy(mut self, policy: PQSecurityPolicy) -> PQResult<Self> {
+    policy.check(&self)?;
+
+    // Reconnections must use the tightened options
+    if let Some((info, _)) = self.credentials.as_mut() {
+      *info = policy.apply(info);
+    }
+
+    self.policy = Some(policy);
+    Ok(self)
+  }
+
+  /// Returns the security policy enforced on this connection, if any.
+  ///
+  pub fn security_policy(&self) -> Option<&PQSecurityPolicy> {
+    self.policy.as_ref()
+  }
+
   /// Returns the connection options used by a live connection.
   ///
   /// See [`PQconninfo`](https://www.postgresql.org/docs/current/libpq-connect.html#LIBPQ-PQCONNINFO)
   ///
   pub fn pq_conninfo(&self) -> PQResult<PQConninfo> {
-    unsafe { PQConninfo::try_from(pq_sys::PQconninfo(self.connection)) }
+    unsafe { PQConninfo::try_from(pq_sys::PQconninfo(self.as_ptr())) }
+  }
+
+  /// Resets the communication channel to the server.
+  ///
+  /// This closes the connection to the server and attempts to establish a new
+  /// connection, using all the same parameters previously used.
+  ///
+  /// Connections made with [`PQConnection::connect_with_credentials`] obtain
+  /// fresh credentials from their provider and connect anew (keeping their
+  /// notice processor and non-blocking mode), rather than reusing their
+  /// original (possibly expired) credentials.
+  ///
+  /// See [`PQreset`](https://www.postgresql.org/docs/current/libpq-connect.html#LIBPQ-PQRESET)
+  ///
+  pub fn pq_reset(&self) -> PQResult<()> {
+    if let Some((info, provider)) = &self.credentials {
+      let fresh = Self::connect_with_provider(info, provider.as_ref())?;
+
+      if let Some(policy) = &self.policy {
+        policy.check(&fresh)?;
+      }
+
+      fresh.pq_setnonblocking(self.pq_isnonblocking())?;
+
+      // Swap the underlying connections, the old one is closed with "fresh"
+      let old = self.connection.swap(fresh.as_ptr(), Ordering::Relaxed);
+      fresh.connection.store(old, Ordering::Relaxed);
+      self.install_notice_processor();
+      fresh.install_notice_processor();
+      self.policy_violated.store(false, Ordering::Relaxed);
+    } else {
+      unsafe { pq_sys::PQreset(self.as_ptr()) };
+      self.policy_violated.store(false, Ordering::Relaxed);
+
+      match self.pq_status() {
+        PQConnectionStatus::Ok => self.enforce_policy()?,
+        _ => return Err(PQError::from(self)),
+      }
+    }
+
+    self.session.reset(self.parameter_statuses());
+    Ok(())
+  }
+
+  /// Check our security policy (if any), marking the connection as failed
+  /// when violated: its status becomes [`PQConnectionStatus::Bad`] and no
+  /// further commands can be sent, until a reset satisfies the policy.
+  ///
+  fn enforce_policy(&self) -> PQResult<()> {
+    let policy = match &self.policy {
+      Some(policy) => policy,
+      None => return Ok(()),
+    };
+
+    let result = policy.check(self);
+    if let Err(error) = &result {
+      debug!("Failing connection {:?}: {}", self, error);
+    }
+
+    self.policy_violated.store(result.is_err(), Ordering::Relaxed);
+    result
+  }
+
+  /// Fail if this connection was marked as failed after violating its
+  /// security policy.
+  ///
+  fn check_policy_violation(&self) -> PQResult<()> {
+    match self.policy_violated.load(Ordering::Relaxed) {
+      false => Ok(()),
+      true => Err(PQError::from("Connection violates its security policy")
+        .with_kind(PQErrorKind::SecurityPolicy)),
+    }
+  }
+
+  /// Connect with the credentials obtained from the specified provider,
+  /// invalidating them if the connection fails.
+  ///
+  fn connect_with_provider(info: &PQConninfo, provider: &dyn PQCredentialProvider) -> PQResult<Self> {
+    let credentials = provider.credentials(info)?;
+
+    PQConnection::try_from(credentials.apply(info)).inspect_err(|error| {
+      debug!("Invalidating credentials from {:?}: {}", provider, error);
+      provider.invalidate();
+    })
   }
 
   /// Sets the current notice processor.
@@ -274,8 +486,7 @@ impl PQConnection {
   /// See [PQnoticeProcessor](https://www.postgresql.org/docs/current/libpq-notice-processing.html)
   /// See [PQnoticeReceiverr](https://www.postgresql.org/docs/current/libpq-notice-processing.html)
   pub fn pq_set_notice_processor(&self, notice_processor: Box<dyn PQNoticeProcessor>) {
-    #[cfg(debug_assertions)]
-    let to_string = format!("{:?}", notice_processor);
+    debug!("Setting up new notice processor: {:?}", notice_processor);
 
     let wrapper = PQNoticeProcessorWrapper::from(notice_processor);
 
@@ -283,15 +494,8 @@ impl PQConnection {
     let pointer = Box::into_raw(boxed);
     let old_pointer = self.notice_processor.swap(pointer, Ordering::Relaxed);
 
-    debug!("Setting up new notice processor at {:?}: {:?}", pointer, to_string);
-
-    unsafe {
-      pq_sys::PQsetNoticeReceiver(
-        self.connection,
-        Some(shared_notice_processor),
-        pointer as *mut c_void,
-      )
-    };
+    debug!("New notice processor set up at {:?}", pointer);
+    self.install_notice_processor();
 
     if old_pointer.is_null() {
       debug!("Not reclaiming old notice processor (null ptr)");
@@ -301,14 +505,40 @@ impl PQConnection {
     }
   }
 
+  /// Return the underlying `PGconn` of this connection.
+  ///
+  fn as_ptr(&self) -> *mut pq_sys::pg_conn {
+    self.connection.load(Ordering::Relaxed)
+  }
+
+  /// Install our current notice processor on the underlying connection.
+  ///
+  fn install_notice_processor(&self) {
+    let pointer = self.notice_processor.load(Ordering::Relaxed);
+
+    unsafe {
+      pq_sys::PQsetNoticeReceiver(
+        self.as_ptr(),
+        Some(shared_notice_processor),
+        pointer as *mut c_void,
+      )
+    };
+  }
+
   // ===== STATUS ==============================================================
 
   /// Returns the status of the connection.
   ///
+  /// Connections that violated their security policy after a reset are
+  /// always reported as [`PQConnectionStatus::Bad`].
+  ///
   /// See [`PQstatus`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQSTATUS)
   ///
   pub fn pq_status(&self) -> PQConnectionStatus {
-    unsafe { pq_sys::PQstatus(self.connection).into() }
+    match self.policy_violated.load(Ordering::Relaxed) {
+      true => PQConnectionStatus::Bad,
+      false => unsafe { pq_sys::PQstatus(self.as_ptr()).into() },
+    }
   }
 
   /// Returns the current in-transaction status of the server.
@@ -316,7 +546,97 @@ impl PQConnection {
   /// See [`PQtransactionStatus`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQTRANSACTIONSTATUS)
   ///
   pub fn pq_transaction_status(&self) -> PQTransactionStatus {
-    unsafe { pq_sys::PQtransactionStatus(self.connection).into() }
+    unsafe { pq_sys::PQtransactionStatus(self.as_ptr()).into() }
+  }
+
+  /// Returns the server host name of the active connection (a host name, an
+  /// IP address, or a socket directory).
+  ///
+  /// With multiple hosts, this is the one actually connected to.
+  ///
+  /// See [`PQhost`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQHOST)
+  ///
+  pub fn pq_host(&self) -> Option<String> {
+    unsafe { to_string_lossy(pq_sys::PQhost(self.as_ptr())) }
+  }
+
+  /// Returns the server IP address of the active connection.
+  ///
+  /// See [`PQhostaddr`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQHOSTADDR)
+  ///
+  pub fn pq_hostaddr(&self) -> Option<String> {
+    unsafe { to_string_lossy(pq_sys::PQhostaddr(self.as_ptr())) }
+  }
+
+  /// Returns the port of the active connection.
+  ///
+  /// See [`PQport`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQPORT)
+  ///
+  pub fn pq_port(&self) -> Option<String> {
+    unsafe { to_string_lossy(pq_sys::PQport(self.as_ptr())) }
+  }
+
+  /// Returns the database name of the connection.
+  ///
+  /// See [`PQdb`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQDB)
+  ///
+  pub fn pq_db(&self) -> Option<String> {
+    unsafe { to_string_lossy(pq_sys::PQdb(self.as_ptr())) }
+  }
+
+  /// Returns the user name of the connection.
+  ///
+  /// See [`PQuser`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQUSER)
+  ///
+  pub fn pq_user(&self) -> Option<String> {
+    unsafe { to_string_lossy(pq_sys::PQuser(self.as_ptr())) }
+  }
+
+  /// Returns the command-line options passed in the connection request.
+  ///
+  /// See [`PQoptions`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQOPTIONS)
+  ///
+  pub fn pq_options(&self) -> Option<String> {
+    unsafe { to_string_lossy(pq_sys::PQoptions(self.as_ptr())) }
+  }
+
+  /// Returns the frontend/backend protocol version in use (currently `3`).
+  ///
+  /// See [`PQprotocolVersion`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQPROTOCOLVERSION)
+  ///
+  pub fn pq_protocol_version(&self) -> i32 {
+    unsafe { pq_sys::PQprotocolVersion(self.as_ptr()) }
+  }
+
+  /// Returns `true` if the connection authentication method required a
+  /// password, but none was available.
+  ///
+  /// See [`PQconnectionNeedsPassword`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQCONNECTIONNEEDSPASSWORD)
+  ///
+  pub fn pq_connection_needs_password(&self) -> bool {
+    unsafe { pq_sys::PQconnectionNeedsPassword(self.as_ptr()) != 0 }
+  }
+
+  /// Returns `true` if the connection authentication method used a password.
+  ///
+  /// See [`PQconnectionUsedPassword`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQCONNECTIONUSEDPASSWORD)
+  ///
+  pub fn pq_connection_used_password(&self) -> bool {
+    unsafe { pq_sys::PQconnectionUsedPassword(self.as_ptr()) != 0 }
+  }
+
+  /// Returns `true` if the connection authentication method used GSSAPI.
+  ///
+  /// See [`PQconnectionUsedGSSAPI`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQCONNECTIONUSEDGSSAPI)
+  ///
+  pub fn pq_connection_used_gssapi(&self) -> bool {
+    unsafe { pq_sys::PQconnectionUsedGSSAPI(self.as_ptr()) != 0 }
+  }
+
+  /// Returns a snapshot of where (and how) this connection is connected to.
+  ///
+  pub fn connection_info(&self) -> PQConnectionInfo {
+    PQConnectionInfo::from(self)
   }
 
   /// Returns the server version as a `String`.
@@ -325,7 +645,7 @@ impl PQConnection {
   ///
   pub fn pq_server_version(&self) -> Option<String> {
     unsafe {
-      match pq_sys::PQserverVersion(self.connection) {
+      match pq_sys::PQserverVersion(self.as_ptr()) {
         0 => None,
         version => {
           let major = version / 10000;
@@ -336,13 +656,35 @@ impl PQConnection {
     }
   }
 
+  /// Looks up a current parameter setting of the server.
+  ///
+  /// See [`PQparameterStatus`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQPARAMETERSTATUS)
+  ///
+  pub fn pq_parameter_status(&self, name: &str) -> Option<String> {
+    unsafe {
+      let string = to_cstring(name);
+      to_string_lossy(pq_sys::PQparameterStatus(self.as_ptr(), string.as_ptr()))
+    }
+  }
+
+  /// Returns the current values of all parameters reported by the server.
+  ///
+  /// See [`REPORTED_PARAMETERS`]
+  ///
+  pub fn parameter_statuses(&self) -> Vec<(String, Option<String>)> {
+    REPORTED_PARAMETERS
+      .iter()
+      .map(|name| (name.to_string(), self.pq_parameter_status(name)))
+      .collect()
+  }
+
   /// Returns the error message most recently generated by an operation on the connection.
   ///
   /// See [`PQerrorMessage`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQERRORMESSAGE)
   ///
   pub fn pq_error_message(&self) -> Option<String> {
     unsafe {
-      let message = pq_sys::PQerrorMessage(self.connection);
+      let message = pq_sys::PQerrorMessage(self.as_ptr());
       match to_string_lossy(message) {
         Some(message) => Some(message.trim().to_string()),
         None => None,
@@ -355,7 +697,7 @@ impl PQConnection {
   /// See [`PQsocket`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQSOCKET)
   ///
   pub fn pq_socket(&self) -> i32 {
-    unsafe { pq_sys::PQsocket(self.connection) }
+    unsafe { pq_sys::PQsocket(self.as_ptr()) }
   }
 
   /// Returns the process ID (PID) of the backend process handling this connection.
@@ -363,7 +705,7 @@ impl PQConnection {
   /// See [`PQbackendPID`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQBACKENDPID)
   ///
   pub fn pq_backend_pid(&self) -> i32 {
-    unsafe { pq_sys::PQbackendPID(self.connection) }
+    unsafe { pq_sys::PQbackendPID(self.as_ptr()) }
   }
 
   /// Returns `true` if the connection uses SSL, `false` if not.
@@ -371,7 +713,7 @@ impl PQConnection {
   /// See [`PQsslInUse`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQSSLINUSE)
   ///
   pub fn pq_ssl_in_use(&self) -> bool {
-    unsafe { pq_sys::PQsslInUse(self.connection) != 0 }
+    unsafe { pq_sys::PQsslInUse(self.as_ptr()) != 0 }
   }
 
   /// Returns SSL-related information about the connection.
@@ -385,14 +727,14 @@ impl PQConnection {
   pub fn pq_ssl_attributes(&self) -> PQResult<Vec<(String, String)>> {
     unsafe {
       let mut strings = Vec::<(String, String)>::new();
-      let raw = pq_sys::PQsslAttributeNames(self.connection);
+      let raw = pq_sys::PQsslAttributeNames(self.as_ptr());
 
       for x in 0.. {
         if (*raw.offset(x)).is_null() {
           break;
         } else {
           let key_ptr = *raw.offset(x);
-          let val_ptr = pq_sys::PQsslAttribute(self.connection, key_ptr);
+          let val_ptr = pq_sys::PQsslAttribute(self.as_ptr(), key_ptr);
           if val_ptr.is_null() {
             continue;
           }
@@ -409,6 +751,31 @@ impl PQConnection {
     }
   }
 
+  /// Returns the certificate chain presented by the server, starting with
+  /// the server's own certificate (empty if SSL is not in use).
+  ///
+  /// See [`PQsslStruct`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQSSLSTRUCT)
+  ///
+  pub fn peer_certificates(&self) -> PQResult<Vec<PQCertificate>> {
+    let name = to_cstring("OpenSSL");
+
+    unsafe {
+      let ssl = pq_sys::PQsslStruct(self.as_ptr(), name.as_ptr());
+      match ssl.is_null() {
+        true => Ok(Vec::new()),
+        false => peer_certificate_chain(ssl as *mut openssl_sys::SSL),
+      }
+    }
+  }
+
+  /// Returns the server's own certificate, if SSL is in use.
+  ///
+  /// See [`PQsslStruct`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQSSLSTRUCT)
+  ///
+  pub fn peer_certificate(&self) -> PQResult<Option<PQCertificate>> {
+    self.peer_certificates().map(|chain| chain.into_iter().next())
+  }
+
   // ===== ASYNC ===============================================================
 
   /// If input is available from the server, consume it.
@@ -417,7 +784,7 @@ impl PQConnection {
   ///
   pub fn pq_consume_input(&self) -> PQResult<()> {
     unsafe {
-      match pq_sys::PQconsumeInput(self.connection) {
+      match pq_sys::PQconsumeInput(self.as_ptr()) {
         1 => Ok(()),
         _ => Err(PQError::from(self)),
       }
@@ -431,7 +798,7 @@ impl PQConnection {
   /// See [`PQisBusy`](https://www.postgresql.org/docs/current/libpq-async.html#LIBPQ-PQISBUSY)
   ///
   pub fn pq_is_busy(&self) -> bool {
-    unsafe { pq_sys::PQisBusy(self.connection) == 1 }
+    unsafe { pq_sys::PQisBusy(self.as_ptr()) == 1 }
   }
 
   /// Sets the nonblocking status of the connection.
@@ -440,7 +807,7 @@ impl PQConnection {
   ///
   pub fn pq_setnonblocking(&self, nonblocking: bool) -> PQResult<()> {
     unsafe {
-      match pq_sys::PQsetnonblocking(self.connection, nonblocking as i32) {
+      match pq_sys::PQsetnonblocking(self.as_ptr(), nonblocking as i32) {
         0 => Ok(()),
         _ => Err(PQError::from(self)),
       }
@@ -452,7 +819,7 @@ impl PQConnection {
   /// See [`PQisnonblocking`](https://www.postgresql.org/docs/current/libpq-async.html#LIBPQ-PQISNONBLOCKING)
   ///
   pub fn pq_isnonblocking(&self) -> bool {
-    unsafe { pq_sys::PQisnonblocking(self.connection) == 1 }
+    unsafe { pq_sys::PQisnonblocking(self.as_ptr()) == 1 }
   }
 
   /// Attempts to flush any queued output data to the server.
@@ -466,7 +833,7 @@ impl PQConnection {
   ///
   pub fn pq_flush(&self) -> PQResult<bool> {
     unsafe {
-      match pq_sys::PQflush(self.connection) {
+      match pq_sys::PQflush(self.as_ptr()) {
         0 => Ok(true), // data is all flushed
         1 => Ok(false), // still some data to flush
         _ => Err(PQError::from(self)),
@@ -474,6 +841,229 @@ impl PQConnection {
     }
   }
 
+  // ===== ESCAPING ============================================================
+
+  /// Escapes a string for use as a literal constant within a SQL command,
+  /// according to the encoding of this connection and wrapping it in quotes.
+  ///
+  /// See [`PQescapeLiteral`](https://www.postgresql.org/docs/current/libpq-exec.html#LIBPQ-PQESCAPELITERAL)
+  ///
+  pub fn pq_escape_literal(&self, value: &str) -> PQResult<String> {
+    self.escape(value, pq_sys::PQescapeLiteral)
+  }
+
+  /// Escapes a string for use as a SQL identifier (such as a table, column,
+  /// or channel name), according to the encoding of this connection and
+  /// wrapping it in double quotes.
+  ///
+  /// See [`PQescapeIdentifier`](https://www.postgresql.org/docs/current/libpq-exec.html#LIBPQ-PQESCAPEIDENTIFIER)
+  ///
+  pub fn pq_escape_identifier(&self, name: &str) -> PQResult<PQIdentifier> {
+    self.escape(name, pq_sys::PQescapeIdentifier)
+      .map(|quoted| PQIdentifier::new(name, quoted))
+  }
+
+  /// Escapes a string for use within a literal constant in a SQL command,
+  /// according to the encoding of this connection, _without_ adding quotes.
+  ///
+  /// See [`PQescapeStringConn`](https://www.postgresql.org/docs/current/libpq-exec.html#LIBPQ-PQESCAPESTRINGCONN)
+  ///
+  pub fn pq_escape_string_conn(&self, value: &str) -> PQResult<String> {
+    if value.contains('\0') {
+      return Err("Unable to escape string containing NUL characters".into());
+    }
+
+    let mut buffer = vec![0 as c_char; value.len() * 2 + 1];
+    let mut error: i32 = 0;
+
+    unsafe {
+      pq_sys::PQescapeStringConn(
+        self.as_ptr(),
+        buffer.as_mut_ptr(),
+        value.as_ptr() as *const c_char,
+        value.len(),
+        &mut error);
+    }
+
+    match error {
+      0 => to_string(buffer.as_ptr()),
+      _ => Err(PQError::from(self)),
+    }
+  }
+
+  /// Escapes binary data for use within a `bytea` literal constant in a SQL
+  /// command, according to the settings of this connection.
+  ///
+  /// See [`PQescapeByteaConn`](https://www.postgresql.org/docs/current/libpq-exec.html#LIBPQ-PQESCAPEBYTEACONN)
+  ///
+  pub fn pq_escape_bytea_conn(&self, data: &[u8]) -> PQResult<String> {
+    let mut length: usize = 0;
+
+    unsafe {
+      let escaped = pq_sys::PQescapeByteaConn(self.as_ptr(), data.as_ptr(), data.len(), &mut length);
+      if escaped.is_null() {
+        return Err(PQError::from(self));
+      }
+
+      let result = to_string(escaped as *const c_char);
+      pq_sys::PQfreemem(escaped as *mut c_void);
+      result
+    }
+  }
+
+  /// Converts the textual representation of binary data (as returned by the
+  /// server for `bytea` columns) into binary data.
+  ///
+  /// See [`PQunescapeBytea`](https://www.postgresql.org/docs/current/libpq-exec.html#LIBPQ-PQUNESCAPEBYTEA)
+  ///
+  pub fn pq_unescape_bytea(value: &str) -> PQResult<Vec<u8>> {
+    let string = to_cstring(value);
+    let mut length: usize = 0;
+
+    unsafe {
+      let data = pq_sys::PQunescapeBytea(string.as_ptr() as *const u8, &mut length);
+      if data.is_null() {
+        return Err("Unable to unescape bytea (out of memory)".into());
+      }
+
+      let result = std::slice::from_raw_parts(data, length).to_vec();
+      pq_sys::PQfreemem(data as *mut c_void);
+      Ok(result)
+    }
+  }
+
+  /// Escape a string with `PQescapeLiteral` or `PQescapeIdentifier`, freeing
+  /// the memory allocated by LibPQ.
+  ///
+  fn escape(
+    &self,
+    value: &str,
+    escape: unsafe extern "C" fn(*mut pq_sys::pg_conn, *const c_char, usize) -> *mut c_char,
+  ) -> PQResult<String> {
+    if value.contains('\0') {
+      return Err("Unable to escape string containing NUL characters".into());
+    }
+
+    unsafe {
+      let escaped = escape(self.as_ptr(), value.as_ptr() as *const c_char, value.len());
+      if escaped.is_null() {
+        return Err(PQError::from(self));
+      }
+
+      let result = to_string(escaped);
+      pq_sys::PQfreemem(escaped as *mut c_void);
+      result
+    }
+  }
+
+  // ===== PASSWORDS ===========================================================
+
+  /// Hashes a password for the specified role, returning the verifier to use
+  /// in `CREATE ROLE` or `ALTER ROLE ... PASSWORD` so that the cleartext
+  /// password is never sent to the server.
+  ///
+  /// When no algorithm is specified, the server's `password_encryption`
+  /// setting is queried and used.
+  ///
+  /// See [`PQencryptPasswordConn`](https://www.postgresql.org/docs/current/libpq-misc.html#LIBPQ-PQENCRYPTPASSWORDCONN)
+  ///
+  pub fn pq_encrypt_password_conn(
+    &self,
+    password: &str,
+    user: &str,
+    algorithm: Option<PQPasswordAlgorithm>,
+  ) -> PQResult<String> {
+    if password.contains('\0') || user.contains('\0') {
+      return Err("Unable to encrypt password or user containing NUL characters".into());
+    }
+
+    // Without an algorithm, LibPQ queries the server
+    if algorithm.is_none() {
+      self.check_policy_violation()?;
+    }
+
+    let password = to_cstring(password);
+    let user = to_cstring(user);
+    let algorithm = algorithm.map(|algorithm| to_cstring(algorithm.as_str()));
+
+    unsafe {
+      let encrypted = pq_sys::PQencryptPasswordConn(
+        self.as_ptr(),
+        password.as_ptr(),
+        user.as_ptr(),
+        algorithm.as_ref().map_or(null_mut(), |algorithm| algorithm.as_ptr() as *mut c_char));
+
+      if encrypted.is_null() {
+        return Err(PQError::from(self));
+      }
+
+      let result = to_string(encrypted);
+      pq_sys::PQfreemem(encrypted as *mut c_void);
+      result
+    }
+  }
+
+  // ===== SYNCHRONOUS OPERATIONS ==============================================
+
+  /// Submits a command to the server and waits for the result.
+  ///
+  /// When multiple commands are submitted, only the last [`PQResponse`] is
+  /// returned.
+  ///
+  /// When the connection has a [result size limit][PQConnection::set_result_size_limit]
+  /// results are read one row at a time, and the command is canceled should
+  /// the limit be exceeded.
+  ///
+  /// See [`PQexec`](https://www.postgresql.org/docs/current/libpq-exec.html#LIBPQ-PQEXEC)
+  ///
+  pub fn pq_exec(&self, command: String) -> PQResult<PQResponse> {
+    if self.result_size_limit().is_some() {
+      return crate::rows::exec_assembled(self, command);
+    }
+
+    self.check_policy_violation()?;
+    self.session.track(&command);
+
+    unsafe {
+      let string = to_cstring(command.as_str());
+      let result = pq_sys::PQexec(self.as_ptr(), string.as_ptr());
+      match result.is_null() {
+        false => PQResponse::try_from(result),
+        true => Err(PQError::from(self)),
+      }
+    }
+  }
+
+  /// Submits a command to the server and waits for all its results, canceling
+  /// it if the specified deadline passes before completion.
+  ///
+  /// See [`exec_with_deadline`][crate::deadline::exec_with_deadline]
+  ///
+  pub fn exec_with_deadline(&self, command: String, deadline: Instant) -> PQResult<Vec<PQResponse>> {
+    crate::deadline::exec_with_deadline(self, command, None, deadline)
+  }
+
+  /// Submits a command and separate parameters to the server and waits for
+  /// all its results, canceling it if the specified deadline passes before
+  /// completion.
+  ///
+  /// See [`exec_with_deadline`][crate::deadline::exec_with_deadline]
+  ///
+  pub fn exec_params_with_deadline(&self, command: String, params: Vec<String>, deadline: Instant) -> PQResult<Vec<PQResponse>> {
+    crate::deadline::exec_with_deadline(self, command, Some(params), deadline)
+  }
+
+  // ===== CANCELING QUERIES ===================================================
+
+  /// Creates a data structure containing the information needed to cancel a
+  /// command issued through this connection.
+  ///
+  /// See [`PQgetCancel`](https://www.postgresql.org/docs/current/libpq-cancel.html#LIBPQ-PQGETCANCEL)
+  ///
+  pub fn pq_get_cancel(&self) -> PQResult<PQCancel> {
+    unsafe { PQCancel::try_from(pq_sys::PQgetCancel(self.as_ptr())) }
+  }
+
   // ===== ASYNCHRONOUS OPERATIONS =============================================
 
   /// Submits a command to the server without waiting for the result(s).
@@ -481,9 +1071,12 @@ impl PQConnection {
   /// See [`PQsendQuery`](https://www.postgresql.org/docs/current/libpq-async.html#LIBPQ-PQSENDQUERY)
   ///
   pub fn pq_send_query(&self, command: String) -> PQResult<()> {
+    self.check_policy_violation()?;
+    self.session.track(&command);
+
     unsafe {
       let string = to_cstring(command.as_str());
-      match pq_sys::PQsendQuery(self.connection, string.as_ptr()) {
+      match pq_sys::PQsendQuery(self.as_ptr(), string.as_ptr()) {
         1 => Ok(()), // successful!
         _ => Err(PQError::from(self)),
       }
@@ -496,12 +1089,15 @@ impl PQConnection {
   /// See [`PQsendQueryParams`](https://www.postgresql.org/docs/current/libpq-async.html#LIBPQ-PQSENDQUERYPARAMS)
   ///
   pub fn pq_send_query_params(&self, command: String, params: Vec<String>) -> PQResult<()> {
+    self.check_policy_violation()?;
+    self.session.track(&command);
+
     unsafe {
       let string = to_cstring(command.as_str());
       let arguments_length = params.len();
       let arguments = NullTerminatedArray::from(params);
       match pq_sys::PQsendQueryParams(
-        self.connection,
+        self.as_ptr(),
         string.as_ptr(),
         arguments_length.try_into().unwrap(),
         std::ptr::null(),
@@ -523,7 +1119,7 @@ impl PQConnection {
   ///
   pub fn pq_get_result(&self) -> Option<PQResponse> {
     unsafe {
-      let result = pq_sys::PQgetResult(self.connection);
+      let result = pq_sys::PQgetResult(self.as_ptr());
       match result.is_null() {
         false => Some(PQResponse::try_from(result).unwrap()),
         true => None,
@@ -531,6 +1127,53 @@ impl PQConnection {
     }
   }
 
+  /// Attempts to make progress on a command previously submitted without
+  /// blocking, flushing any queued output and consuming any available input.
+  ///
+  /// When the connection is in nonblocking mode, this never blocks: should no
+  /// result be available yet, the interest to wait for before calling this
+  /// again is returned.
+  ///
+  /// See [`PQflush`](https://www.postgresql.org/docs/current/libpq-async.html#LIBPQ-PQFLUSH)
+  /// See [`PQconsumeInput`](https://www.postgresql.org/docs/current/libpq-async.html#LIBPQ-PQCONSUMEINPUT)
+  /// See [`PQisBusy`](https://www.postgresql.org/docs/current/libpq-async.html#LIBPQ-PQISBUSY)
+  ///
+  pub fn try_get_result(&self) -> PQResult<PQNextResult> {
+    if ! self.pq_flush()? {
+      return Ok(PQNextResult::Wait(PQPollingInterest::Writable));
+    }
+
+    self.pq_consume_input()?;
+
+    if self.pq_is_busy() {
+      return Ok(PQNextResult::Wait(PQPollingInterest::Readable));
+    }
+
+    match self.pq_get_result() {
+      Some(response) => Ok(PQNextResult::Ready(response)),
+      None => Ok(PQNextResult::Done),
+    }
+  }
+
+  /// Returns the next unhandled notification received from the server, if any.
+  ///
+  /// See [PQnotifies](https://www.postgresql.org/docs/current/libpq-notify.html)
+  ///
+  pub fn next_notification(&self) -> PQResult<Option<PQNotification>> {
+    unsafe {
+      // The "pgNotify" struct has a "next" pointer to it, but LibPQ's own
+      // source explicitly mentions that it shouldn't be used in client code.
+      let result = pq_sys::PQnotifies(self.as_ptr());
+      if result.is_null() {
+        return Ok(None);
+      }
+
+      let notification = PQNotification::try_from(result);
+      pq_sys::PQfreemem(result as *mut c_void);
+      notification.map(Some)
+    }
+  }
+
   /// Returns a vector of all unhandled notifications received from the server.
   ///
   /// See [PQnotifies](https://www.postgresql.org/docs/current/libpq-notify.html)
@@ -538,21 +1181,390 @@ impl PQConnection {
   pub fn pq_notifies(&self) -> PQResult<Vec<PQNotification>> {
     let mut vec = Vec::<PQNotification>::new();
 
-    unsafe {
-      for _ in 0.. {
-        // The "pgNotify" struct has a "next" pointer to it, but LibPQ's own
-        // source explicitly mentions that it shouldn't be used in client code.
-        let result = pq_sys::PQnotifies(self.connection);
-        if result.is_null() {
-          break;
-        }
+    while let Some(notification) = self.next_notification()? {
+      vec.push(notification);
+    }
+
+    Ok(vec)
+  }
+
+  /// Wait for the next notification from the server, up to the specified
+  /// timeout (or forever if no timeout was specified), returning `None` if
+  /// the timeout expired.
+  ///
+  /// See [Asynchronous Notification](https://www.postgresql.org/docs/current/libpq-notify.html)
+  ///
+  pub fn wait_for_notification(&self, timeout: Option<Duration>) -> PQResult<Option<PQNotification>> {
+    // Timeouts too large to be represented simply mean "forever"
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+
+    loop {
+      self.pq_consume_input()?;
+
+      if let Some(notification) = self.next_notification()? {
+        return Ok(Some(notification));
+      }
+
+      let remaining = match deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+          Some(remaining) => Some(remaining),
+          None => return Ok(None),
+        },
+        None => None,
+      };
 
-        vec.push(PQNotification::try_from(result)?);
-        pq_sys::PQfreemem(result as *mut c_void);
+      if ! self.poll(PQPollingInterest::Readable, remaining)? {
+        return Ok(None);
       }
     }
+  }
 
-    Ok(vec)
+  /// Wait for the next notification from the server on the specified channel,
+  /// up to the specified timeout (or forever if no timeout was specified),
+  /// returning `None` if the timeout expired.
+  ///
+  /// Notifications received on any other channel are **discarded**.
+  ///
+  pub fn wait_for_notification_on(&self, channel: &str, timeout: Option<Duration>) -> PQResult<Option<PQNotification>> {
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+
+    loop {
+      let remaining = deadline
+        .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+
+      match self.wait_for_notification(remaining)? {
+        Some(notification) if notification.channel == channel => return Ok(Some(notification)),
+        Some(notification) => debug!("Discarding notification {:?}", notification),
+        None => return Ok(None),
+      }
+    }
+  }
+
+  // ===== FUTURES =============================================================
+
+  /// Return a [`Future`][std::future::Future] sending a command, and
+  /// resolving to all its [`PQResponse`]s.
+  ///
+  /// The connection is switched to nonblocking mode when first polled.
+  ///
+  /// See [`PQQueryFuture`]
+  ///
+  pub fn query_async(&self, command: String) -> PQQueryFuture<'_> {
+    PQQueryFuture::new(self, command, None)
+  }
+
+  /// Return a [`Future`][std::future::Future] sending a command with
+  /// parameters, and resolving to all its [`PQResponse`]s.
+  ///
+  /// The connection is switched to nonblocking mode when first polled.
+  ///
+  /// See [`PQQueryFuture`]
+  ///
+  pub fn query_params_async(&self, command: String, params: Vec<String>) -> PQQueryFuture<'_> {
+    PQQueryFuture::new(self, command, Some(params))
+  }
+
+  /// Return a [`Stream`][futures_core::Stream] sending a command, and
+  /// yielding each of its [`PQResponse`]s as they become available.
+  ///
+  /// The connection is switched to nonblocking mode when first polled.
+  ///
+  /// See [`PQResponseStream`]
+  ///
+  pub fn stream_async(&self, command: String) -> PQResponseStream<'_> {
+    PQResponseStream::new(self, command, None)
+  }
+
+  // ===== TRANSACTIONS ========================================================
+
+  /// Start a new [`PQTransaction`] on this connection.
+  ///
+  /// See [`BEGIN`](https://www.postgresql.org/docs/current/sql-begin.html)
+  ///
+  pub fn transaction(&self, options: PQTransactionOptions) -> PQResult<PQTransaction<'_>> {
+    PQTransaction::begin(self, options)
+  }
+
+  /// Run the specified closure in a [`PQTransaction`], retrying it whenever it
+  /// fails because of a serialization failure or deadlock.
+  ///
+  /// See [`retry_transaction`][crate::retry::retry_transaction]
+  ///
+  pub fn retry_transaction<T, F>(
+    &self,
+    options: PQTransactionOptions,
+    retry: PQRetryOptions,
+    closure: F,
+  ) -> PQResult<PQRetryOutcome<T>>
+  where
+    F: FnMut(&PQTransaction) -> PQResult<T>,
+  {
+    crate::retry::retry_transaction(self, options, retry, closure)
+  }
+
+  // ===== CURSORS =============================================================
+
+  /// Declare a new server-side [`PQCursor`] `WITH HOLD` for the specified
+  /// query, outside of any transaction.
+  ///
+  /// Cursors without hold (or declared within a transaction) must be declared
+  /// with [`PQTransaction::cursor`], so that they can not outlive it.
+  ///
+  /// See [`DECLARE`](https://www.postgresql.org/docs/current/sql-declare.html)
+  ///
+  pub fn cursor(&self, query: &str, options: PQCursorOptions) -> PQResult<PQCursor<'_>> {
+    if ! options.hold {
+      return Err("Cursors without hold must be declared in a transaction".into());
+    }
+
+    match self.pq_transaction_status() {
+      PQTransactionStatus::Idle => PQCursor::declare(self, query, options),
+      status => Err(format!("Cursors within a transaction must be declared on it (status={:?})", status).into()),
+    }
+  }
+
+  // ===== SESSION STATE =======================================================
+
+  /// Explicitly mark the session state as changed, so that the next call to
+  /// [`PQConnection::reset_session_if_dirty`] will reset it.
+  ///
+  pub fn mark_session_dirty(&self) {
+    self.session.mark_dirty()
+  }
+
+  /// Returns `true` if the session state _might_ have been changed since the
+  /// connection was established (or its session last reset).
+  ///
+  /// A session is considered dirty when it's not idle (in a transaction, or
+  /// running a command), when any of the parameters reported by the server
+  /// changed, or when a command that might change its state (e.g. `SET`,
+  /// `LISTEN`, `PREPARE`, `CREATE TEMP TABLE`, ...) was submitted.
+  ///
+  pub fn is_session_dirty(&self) -> bool {
+    self.session.is_marked_dirty()
+      || self.pq_transaction_status() != PQTransactionStatus::Idle
+      || self.session.parameters_changed(&self.parameter_statuses())
+  }
+
+  /// Reset the session state, rolling back any transaction in progress.
+  ///
+  pub fn reset_session(&self, reset: &PQSessionReset) -> PQResult<()> {
+    match self.pq_transaction_status() {
+      PQTransactionStatus::InTransaction |
+      PQTransactionStatus::InError => {
+        self.pq_exec("ROLLBACK".to_string())?.into_result()?;
+      },
+      _ => (),
+    }
+
+    self.pq_exec(reset.to_command())?.into_result()?;
+    self.session.reset(self.parameter_statuses());
+    Ok(())
+  }
+
+  /// Reset the session state only if it's [dirty][PQConnection::is_session_dirty],
+  /// returning `true` if it was reset.
+  ///
+  pub fn reset_session_if_dirty(&self, reset: &PQSessionReset) -> PQResult<bool> {
+    match self.is_session_dirty() {
+      true => self.reset_session(reset).map(|_| true),
+      false => Ok(false),
+    }
+  }
+
+  // ===== TWO-PHASE COMMIT ====================================================
+
+  /// Commit a transaction previously prepared for two-phase commit.
+  ///
+  /// See [`COMMIT PREPARED`](https://www.postgresql.org/docs/current/sql-commit-prepared.html)
+  ///
+  pub fn commit_prepared(&self, gid: &PQGlobalTransactionId) -> PQResult<()> {
+    crate::twophase::commit_prepared(self, gid)
+  }
+
+  /// Roll back a transaction previously prepared for two-phase commit.
+  ///
+  /// See [`ROLLBACK PREPARED`](https://www.postgresql.org/docs/current/sql-rollback-prepared.html)
+  ///
+  pub fn rollback_prepared(&self, gid: &PQGlobalTransactionId) -> PQResult<()> {
+    crate::twophase::rollback_prepared(self, gid)
+  }
+
+  /// List all transactions prepared for two-phase commit in the current
+  /// database.
+  ///
+  /// See [`prepared_transactions`][crate::twophase::prepared_transactions]
+  ///
+  pub fn prepared_transactions(&self) -> PQResult<Vec<PQPreparedTransaction>> {
+    crate::twophase::prepared_transactions(self)
+  }
+
+  /// Resolve all transactions prepared in the current database according to
+  /// the decision function specified.
+  ///
+  /// See [`recover_prepared_transactions`][crate::twophase::recover_prepared_transactions]
+  ///
+  pub fn recover_prepared_transactions<F>(
+    &self,
+    decide: F,
+  ) -> PQResult<Vec<(PQPreparedTransaction, PQPreparedDecision, PQResult<()>)>>
+  where
+    F: FnMut(&PQPreparedTransaction) -> PQPreparedDecision,
+  {
+    crate::twophase::recover_prepared_transactions(self, decide)
+  }
+
+  // ===== LARGE OBJECTS =======================================================
+
+  /// Creates a new large object, returning its OID.
+  ///
+  /// See [`lo_creat`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-CREATE)
+  ///
+  pub fn lo_creat(&self) -> PQResult<u32> {
+    self.lo_verify()?;
+
+    let mode = PQLargeObjectMode::ReadWrite.as_flags();
+    match unsafe { pq_sys::lo_creat(self.as_ptr(), mode) } {
+      0 => Err(PQError::from(self)),
+      oid => Ok(oid),
+    }
+  }
+
+  /// Removes the large object with the specified OID from the database.
+  ///
+  /// See [`lo_unlink`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-UNLINK)
+  ///
+  pub fn lo_unlink(&self, oid: u32) -> PQResult<()> {
+    self.lo_verify()?;
+
+    match unsafe { pq_sys::lo_unlink(self.as_ptr(), oid) } {
+      -1 => Err(PQError::from(self)),
+      _ => Ok(()),
+    }
+  }
+
+  /// Imports a file (on the client's filesystem) as a new large object,
+  /// returning its OID.
+  ///
+  /// See [`lo_import`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-IMPORT)
+  ///
+  pub fn lo_import(&self, path: &Path) -> PQResult<u32> {
+    self.lo_verify()?;
+
+    let filename = path_to_cstring(path)?;
+    match unsafe { pq_sys::lo_import(self.as_ptr(), filename.as_ptr()) } {
+      0 => Err(PQError::from(self)),
+      oid => Ok(oid),
+    }
+  }
+
+  /// Exports the large object with the specified OID to a file (on the
+  /// client's filesystem).
+  ///
+  /// See [`lo_export`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-EXPORT)
+  ///
+  pub fn lo_export(&self, oid: u32, path: &Path) -> PQResult<()> {
+    self.lo_verify()?;
+
+    let filename = path_to_cstring(path)?;
+    match unsafe { pq_sys::lo_export(self.as_ptr(), oid, filename.as_ptr()) } {
+      -1 => Err(PQError::from(self)),
+      _ => Ok(()),
+    }
+  }
+
+  /// Make sure large objects can be used, that is we're in a transaction.
+  ///
+  pub(crate) fn lo_verify(&self) -> PQResult<()> {
+    match self.pq_transaction_status() {
+      PQTransactionStatus::InTransaction => Ok(()),
+      status => Err(format!("Large objects can only be used within a transaction (status={:?})", status).into()),
+    }
+  }
+
+  /// Opens a large object, returning its descriptor.
+  ///
+  /// See [`lo_open`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-OPEN)
+  ///
+  pub(crate) fn lo_open_descriptor(&self, oid: u32, mode: i32) -> PQResult<i32> {
+    self.lo_verify()?;
+
+    match unsafe { pq_sys::lo_open(self.as_ptr(), oid, mode) } {
+      -1 => Err(PQError::from(self)),
+      fd => Ok(fd),
+    }
+  }
+
+  /// Closes a large object descriptor.
+  ///
+  /// See [`lo_close`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-CLOSE)
+  ///
+  pub(crate) fn lo_close(&self, fd: i32) -> PQResult<()> {
+    match unsafe { pq_sys::lo_close(self.as_ptr(), fd) } {
+      -1 => Err(PQError::from(self)),
+      _ => Ok(()),
+    }
+  }
+
+  /// Reads from a large object descriptor.
+  ///
+  /// See [`lo_read`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-READ)
+  ///
+  pub(crate) fn lo_read(&self, fd: i32, buffer: &mut [u8]) -> PQResult<usize> {
+    // Reads are limited to INT_MAX bytes by LibPQ
+    let length = buffer.len().min(i32::MAX as usize);
+
+    match unsafe { pq_sys::lo_read(self.as_ptr(), fd, buffer.as_mut_ptr() as *mut c_char, length) } {
+      -1 => Err(PQError::from(self)),
+      read => Ok(read as usize),
+    }
+  }
+
+  /// Writes to a large object descriptor.
+  ///
+  /// See [`lo_write`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-WRITE)
+  ///
+  pub(crate) fn lo_write(&self, fd: i32, buffer: &[u8]) -> PQResult<usize> {
+    // Writes are limited to INT_MAX bytes by LibPQ
+    let length = buffer.len().min(i32::MAX as usize);
+
+    match unsafe { pq_sys::lo_write(self.as_ptr(), fd, buffer.as_ptr() as *const c_char, length) } {
+      -1 => Err(PQError::from(self)),
+      written => Ok(written as usize),
+    }
+  }
+
+  /// Changes the current position of a large object descriptor.
+  ///
+  /// See [`lo_lseek64`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-SEEK)
+  ///
+  pub(crate) fn lo_lseek64(&self, fd: i32, offset: i64, whence: i32) -> PQResult<i64> {
+    match unsafe { pq_sys::lo_lseek64(self.as_ptr(), fd, offset, whence) } {
+      -1 => Err(PQError::from(self)),
+      position => Ok(position),
+    }
+  }
+
+  /// Returns the current position of a large object descriptor.
+  ///
+  /// See [`lo_tell64`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-TELL)
+  ///
+  pub(crate) fn lo_tell64(&self, fd: i32) -> PQResult<i64> {
+    match unsafe { pq_sys::lo_tell64(self.as_ptr(), fd) } {
+      -1 => Err(PQError::from(self)),
+      position => Ok(position),
+    }
+  }
+
+  /// Truncates a large object descriptor to the specified length.
+  ///
+  /// See [`lo_truncate64`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-TRUNCATE)
+  ///
+  pub(crate) fn lo_truncate64(&self, fd: i32, length: i64) -> PQResult<()> {
+    match unsafe { pq_sys::lo_truncate64(self.as_ptr(), fd, length) } {
+      -1 => Err(PQError::from(self)),
+      _ => Ok(()),
+    }
   }
 
   // ===== SINGLE ROW MODE =====================================================
@@ -562,15 +1574,88 @@ impl PQConnection {
   /// See [`PQsetSingleRowMode`](https://www.postgresql.org/docs/current/libpq-single-row-mode.html#LIBPQ-PQSETSINGLEROWMODE)
   ///
   pub fn pq_set_single_row_mode(&self) -> bool {
-    unsafe { pq_sys::PQsetSingleRowMode(self.connection) == 1 }
+    unsafe { pq_sys::PQsetSingleRowMode(self.as_ptr()) == 1 }
+  }
+
+  /// Set the memory budget (in bytes) for the results of each command, or
+  /// `None` for no limit.
+  ///
+  /// When set, the results of [`PQConnection::pq_exec`], [`PQConnection::exec_with_deadline`],
+  /// [`PQConnection::query_async`] (and their variants) are read one row at a
+  /// time and reassembled, so that a command can be canceled as soon as its
+  /// results exceed the limit, returning an error of kind [`PQErrorKind::ResultTooLarge`].
+  ///
+  /// This is also the default limit for [`PQRowStream`]s.
+  ///
+  /// See [`PQRowStream::with_limit`]
+  ///
+  pub fn set_result_size_limit(&self, limit: Option<usize>) {
+    self.result_size_limit.store(limit.unwrap_or(usize::MAX), Ordering::Relaxed);
+  }
+
+  /// Return the memory budget (in bytes) for the results of each command,
+  /// if any.
+  ///
+  /// See [`PQConnection::set_result_size_limit`]
+  ///
+  pub fn result_size_limit(&self) -> Option<usize> {
+    match self.result_size_limit.load(Ordering::Relaxed) {
+      usize::MAX => None,
+      limit => Some(limit),
+    }
+  }
+
+  /// Execute a command in single-row mode, collecting all its rows as long
+  /// as their total size does not exceed the specified limit.
+  ///
+  /// Should the limit be exceeded, the command is canceled and an error of
+  /// kind [`PQErrorKind::ResultTooLarge`] is returned.
+  ///
+  pub fn exec_with_limit(&self, command: String, limit: usize) -> PQResult<Vec<PQRow>> {
+    crate::rows::exec_with_limit(self, command, None, limit)
+  }
+
+  /// Execute a command with parameters in single-row mode, collecting all its
+  /// rows as long as their total size does not exceed the specified limit.
+  ///
+  /// See [`PQConnection::exec_with_limit`]
+  ///
+  pub fn exec_params_with_limit(&self, command: String, params: Vec<String>, limit: usize) -> PQResult<Vec<PQRow>> {
+    crate::rows::exec_with_limit(self, command, Some(params), limit)
+  }
+
+  /// Send a command, returning a [`PQRowStream`] reading its results one
+  /// row at a time.
+  ///
+  /// See [`PQRowStream`]
+  ///
+  pub fn stream_rows(&self, command: String) -> PQResult<PQRowStream<'_>> {
+    PQRowStream::new(self, command, None)
+  }
+
+  /// Send a command with parameters, returning a [`PQRowStream`] reading its
+  /// results one row at a time.
+  ///
+  /// See [`PQRowStream`]
+  ///
+  pub fn stream_rows_params(&self, command: String, params: Vec<String>) -> PQResult<PQRowStream<'_>> {
+    PQRowStream::new(self, command, Some(params))
   }
 
   // ===== POLLING =============================================================
 
-  /// Wait until reads from or writes to the connection will not block.
+  /// Wait until reads from or writes to the connection will not block, up to
+  /// the specified timeout (or forever if no timeout was specified).
+  ///
+  /// Returns `true` if the connection is ready, or `false` if the timeout
+  /// expired.
   ///
-  pub fn poll(&self, interest: PQPollingInterest, timeout: Option<Duration>) -> PQResult<()> {
+  /// This creates a new poller on each call: to wait on many connections at
+  /// once, use a [`PQReactor`][crate::reactor::PQReactor] instead.
+  ///
+  pub fn poll(&self, interest: PQPollingInterest, timeout: Option<Duration>) -> PQResult<bool> {
     let key = debug_id();
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
 
     let event = match interest {
       PQPollingInterest::Readable => Event::readable(key),
@@ -581,7 +1666,7 @@ impl PQConnection {
       .map_err(| err | format!("Error creating poller: {}", err))?;
 
     let source = unsafe {
-      let fd = pq_sys::PQsocket(self.connection);
+      let fd = pq_sys::PQsocket(self.as_ptr());
       let source = BorrowedFd::borrow_raw(fd);
       poller.add(&source, event)
         .map_err(| err | format!("Error adding to poller: {}", err))?;
@@ -591,10 +1676,21 @@ impl PQConnection {
     let result = 'outer: loop {
       let mut events = Events::new();
 
-      poller.wait(&mut events, timeout)
-        .map_err(| err | format!("Error waiting on poller: {}", err))?;
+      // Always wait at least once, even if the deadline already passed
+      let remaining = deadline
+        .map(|deadline| deadline.saturating_duration_since(Instant::now()));
 
-      if events.is_empty() { println!("NOPE"); continue 'outer }
+      if let Err(err) = poller.wait(&mut events, remaining) {
+        break 'outer Err(format!("Error waiting on poller: {}", err).into())
+      }
+
+      // No events: either our timeout expired, or we woke up spuriously
+      if events.is_empty() {
+        match deadline {
+          Some(deadline) if Instant::now() >= deadline => break 'outer Ok(false),
+          _ => continue 'outer,
+        }
+      }
 
       'inner: for event in events.iter() {
         if event.key != key { continue 'inner; }
@@ -602,8 +1698,8 @@ impl PQConnection {
         if event.is_err() == Some(true) { break 'outer Err(PQError::from("Connection error")) }
 
         match interest {
-          PQPollingInterest::Readable => if event.readable { println!("YES R"); break 'outer Ok(()) },
-          PQPollingInterest::Writable => if event.writable { println!("YES W"); break 'outer Ok(()) },
+          PQPollingInterest::Readable => if event.readable { break 'outer Ok(true) },
+          PQPollingInterest::Writable => if event.writable { break 'outer Ok(true) },
         }
       }
     };
@@ -0,0 +1,248 @@
+//! Credentials supplied right before each connection attempt.
+
+use crate::conninfo::PQConninfo;
+use crate::debug::*;
+use crate::errors::*;
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// What to display in place of passwords.
+static REDACTED: &str = "********";
+
+/* ========================================================================== *
+ * CREDENTIALS                                                                *
+ * ========================================================================== */
+
+/// A (possibly short-lived) password, and optionally the user it belongs to.
+///
+#[derive(Clone, PartialEq, Eq)]
+pub struct PQCredentials {
+  /// The user name, or `None` to keep the one of the connection options.
+  pub user: Option<String>,
+  /// The password (or token).
+  pub password: String,
+  /// When the password expires, or `None` if it never does.
+  pub expires: Option<Instant>,
+}
+
+impl Debug for PQCredentials {
+  /// Format these [`PQCredentials`] with their password redacted.
+  ///
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("PQCredentials")
+      .field("user", &self.user)
+      .field("password", &REDACTED)
+      .field("expires", &self.expires)
+      .finish()
+  }
+}
+
+impl PQCredentials {
+  /// Create new [`PQCredentials`] with the specified password, never expiring.
+  ///
+  pub fn new(password: &str) -> Self {
+    Self { user: None, password: password.to_string(), expires: None }
+  }
+
+  /// Set the user these credentials belong to.
+  ///
+  pub fn user(mut self, user: &str) -> Self {
+    self.user = Some(user.to_string());
+    self
+  }
+
+  /// Set the instant these credentials expire at.
+  ///
+  pub fn expires_at(mut self, expires: Instant) -> Self {
+    self.expires = Some(expires);
+    self
+  }
+
+  /// Set these credentials to expire after the specified duration (or never,
+  /// if the duration is too large to be represented).
+  ///
+  pub fn expires_in(self, duration: Duration) -> Self {
+    match Instant::now().checked_add(duration) {
+      Some(expires) => self.expires_at(expires),
+      None => self,
+    }
+  }
+
+  /// Check whether these credentials expire within the specified duration.
+  ///
+  pub fn expires_within(&self, duration: Duration) -> bool {
+    match self.expires {
+      Some(expires) => Instant::now().checked_add(duration).is_none_or(|limit| limit >= expires),
+      None => false,
+    }
+  }
+
+  /// Return a copy of the specified [`PQConninfo`] using these credentials.
+  ///
+  pub fn apply(&self, info: &PQConninfo) -> PQConninfo {
+    let user = self.user.as_ref().map(|user| ("user".to_string(), user.clone()));
+    let password = ("password".to_string(), self.password.clone());
+
+    info.iter()
+      .filter(|(key, _)| key != "password" && (self.user.is_none() || key != "user"))
+      .cloned()
+      .chain(user)
+      .chain([ password ])
+      .collect()
+  }
+}
+
+/* ========================================================================== *
+ * PROVIDERS                                                                  *
+ * ========================================================================== */
+
+/// The trait that defines a provider of [`PQCredentials`], invoked right
+/// before each attempt to connect (or reconnect) to the server.
+///
+pub trait PQCredentialProvider: Debug + Send + Sync {
+  /// Return the credentials to connect with the specified options.
+  ///
+  fn credentials(&self, info: &PQConninfo) -> PQResult<PQCredentials>;
+
+  /// Discard any cached credentials, as connecting with them failed.
+  ///
+  fn invalidate(&self) {}
+}
+
+/// A [`PQCredentialProvider`] caching the credentials of another one until
+/// they are about to expire.
+///
+/// Credentials are cached regardless of the connection options they were
+/// requested with, and are refreshed when they expire within the configured
+/// margin (30 seconds by default) or after a failed connection attempt.
+///
+pub struct PQCachedCredentials<P: PQCredentialProvider> {
+  id: usize,
+  provider: P,
+  margin: Duration,
+  cached: Mutex<Option<PQCredentials>>,
+}
+
+impl <P: PQCredentialProvider> Debug for PQCachedCredentials<P> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("PQCachedCredentials")
+      .field("id", &self.id)
+      .field("provider", &self.provider)
+      .field("margin", &self.margin)
+      .finish()
+  }
+}
+
+impl <P: PQCredentialProvider> PQCachedCredentials<P> {
+  /// Create a new [`PQCachedCredentials`] wrapping the specified provider.
+  ///
+  pub fn new(provider: P) -> Self {
+    Self {
+      id: debug_id(),
+      provider,
+      margin: Duration::from_secs(30),
+      cached: Mutex::new(None),
+    }
+  }
+
+  /// Set how long before their expiry cached credentials are refreshed.
+  ///
+  pub fn margin(mut self, margin: Duration) -> Self {
+    self.margin = margin;
+    self
+  }
+
+  /// Lock our cache, ignoring poisoning (it's always consistent).
+  ///
+  fn cached(&self) -> std::sync::MutexGuard<'_, Option<PQCredentials>> {
+    self.cached.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+  }
+}
+
+impl <P: PQCredentialProvider> PQCredentialProvider for PQCachedCredentials<P> {
+  fn credentials(&self, info: &PQConninfo) -> PQResult<PQCredentials> {
+    let mut cached = self.cached();
+
+    if let Some(credentials) = cached.as_ref() {
+      if ! credentials.expires_within(self.margin) {
+        return Ok(credentials.clone());
+      }
+    }
+
+    debug!("Refreshing credentials in {:?}", self);
+    let credentials = self.provider.credentials(info)?;
+    *cached = Some(credentials.clone());
+    Ok(credentials)
+  }
+
+  fn invalidate(&self) {
+    self.cached().take();
+    self.provider.invalidate();
+  }
+}
+
+/* ========================================================================== *
+ * TESTS                                                                      *
+ * ========================================================================== */
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn credentials_expiry() {
+    let minute = Duration::from_secs(60);
+
+    // Never expiring, regardless of the margin
+    let credentials = PQCredentials::new("secret");
+    assert!(!credentials.expires_within(Duration::ZERO));
+    assert!(!credentials.expires_within(Duration::MAX));
+
+    // Too large to be represented, hence never expiring
+    let credentials = PQCredentials::new("secret").expires_in(Duration::MAX);
+    assert_eq!(credentials.expires, None);
+
+    // Margins before and after the expiry
+    let credentials = PQCredentials::new("secret").expires_in(minute * 10);
+    assert!(!credentials.expires_within(Duration::ZERO));
+    assert!(!credentials.expires_within(minute));
+    assert!(credentials.expires_within(minute * 20));
+    assert!(credentials.expires_within(Duration::MAX));
+
+    // Expiring right now (the boundary is inclusive), or in the past
+    let credentials = PQCredentials::new("secret").expires_at(Instant::now());
+    assert!(credentials.expires_within(Duration::ZERO));
+    let credentials = PQCredentials::new("secret").expires_at(Instant::now().checked_sub(minute).unwrap_or_else(Instant::now));
+    assert!(credentials.expires_within(Duration::ZERO));
+  }
+
+  #[test]
+  fn credentials_apply() {
+    let info = [ ("host", "localhost"), ("user", "alice"), ("password", "old") ]
+      .iter()
+      .map(|(key, value)| (key.to_string(), value.to_string()))
+      .collect::<PQConninfo>();
+
+    // Without a user only the password is replaced
+    let applied = PQCredentials::new("new").apply(&info);
+    assert_eq!(applied.get("host"), Some("localhost"));
+    assert_eq!(applied.get("user"), Some("alice"));
+    assert_eq!(applied.get("password"), Some("new"));
+    assert_eq!(applied.iter().count(), 3);
+
+    // With a user both are replaced
+    let applied = PQCredentials::new("new").user("bob").apply(&info);
+    assert_eq!(applied.get("host"), Some("localhost"));
+    assert_eq!(applied.get("user"), Some("bob"));
+    assert_eq!(applied.get("password"), Some("new"));
+    assert_eq!(applied.iter().count(), 3);
+
+    // Missing options are added, and the original is left untouched
+    let applied = PQCredentials::new("new").user("bob").apply(&PQConninfo::default());
+    assert_eq!(applied.get("user"), Some("bob"));
+    assert_eq!(applied.get("password"), Some("new"));
+    assert_eq!(info.get("password"), Some("old"));
+  }
+}
@@ -0,0 +1,306 @@
+//! Server-side cursors, reading huge results in batches.
+
+use crate::connection::PQConnection;
+use crate::connection::PQTransactionStatus;
+use crate::debug::*;
+use crate::errors::*;
+use crate::response::PQResponse;
+use crate::response::PQRow;
+use std::collections::VecDeque;
+
+/* ========================================================================== *
+ * OPTIONS                                                                    *
+ * ========================================================================== */
+
+/// Options used when declaring a new [`PQCursor`].
+///
+/// See [`DECLARE`](https://www.postgresql.org/docs/current/sql-declare.html)
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PQCursorOptions {
+  /// Whether the cursor can be used to retrieve rows in a nonsequential
+  /// fashion (e.g. backwards).
+  pub scroll: bool,
+  /// Whether the cursor can continue to be used after the transaction that
+  /// created it successfully commits.
+  pub hold: bool,
+  /// The number of rows fetched at once while iterating.
+  pub batch_size: u32,
+}
+
+impl Default for PQCursorOptions {
+  fn default() -> Self {
+    Self { scroll: false, hold: false, batch_size: 1000 }
+  }
+}
+
+impl PQCursorOptions {
+  /// Set whether the cursor can scroll backwards.
+  ///
+  pub fn scroll(mut self, scroll: bool) -> Self {
+    self.scroll = scroll;
+    self
+  }
+
+  /// Set whether the cursor is held after its transaction commits.
+  ///
+  pub fn hold(mut self, hold: bool) -> Self {
+    self.hold = hold;
+    self
+  }
+
+  /// Set the number of rows fetched at once while iterating.
+  ///
+  pub fn batch_size(mut self, batch_size: u32) -> Self {
+    self.batch_size = batch_size;
+    self
+  }
+
+  /// Returns the `DECLARE` command for a cursor named and defined as given.
+  ///
+  pub fn to_declare_command(&self, name: &str, query: &str) -> String {
+    let scroll = match self.scroll {
+      true => "SCROLL",
+      false => "NO SCROLL",
+    };
+
+    let hold = match self.hold {
+      true => "WITH HOLD",
+      false => "WITHOUT HOLD",
+    };
+
+    format!("DECLARE {} {} CURSOR {} FOR {}", name, scroll, hold, query)
+  }
+}
+
+/// The direction of a `FETCH` or `MOVE` on a [`PQCursor`].
+///
+/// See [`FETCH`](https://www.postgresql.org/docs/current/sql-fetch.html)
+///
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub enum PQCursorDirection {
+  /// The next row.
+  Next,
+  /// The prior row.
+  Prior,
+  /// The first row of the query.
+  First,
+  /// The last row of the query.
+  Last,
+  /// The row at the specified position (negative counting from the end).
+  Absolute(i64),
+  /// The row at the specified offset from the current one.
+  Relative(i64),
+  /// The next N rows.
+  Forward(u64),
+  /// All remaining rows.
+  ForwardAll,
+  /// The prior N rows (scanning backwards).
+  Backward(u64),
+  /// All prior rows (scanning backwards).
+  BackwardAll,
+}
+
+impl PQCursorDirection {
+  /// Returns the SQL representation of this direction.
+  ///
+  pub fn as_sql(&self) -> String {
+    match self {
+      Self::Next => "NEXT".to_string(),
+      Self::Prior => "PRIOR".to_string(),
+      Self::First => "FIRST".to_string(),
+      Self::Last => "LAST".to_string(),
+      Self::Absolute(count) => format!("ABSOLUTE {}", count),
+      Self::Relative(count) => format!("RELATIVE {}", count),
+      Self::Forward(count) => format!("FORWARD {}", count),
+      Self::ForwardAll => "FORWARD ALL".to_string(),
+      Self::Backward(count) => format!("BACKWARD {}", count),
+      Self::BackwardAll => "BACKWARD ALL".to_string(),
+    }
+  }
+
+  /// Returns `true` if this direction only ever moves the cursor forward,
+  /// and can therefore be used with cursors declared without `SCROLL`.
+  ///
+  pub fn is_forward(&self) -> bool {
+    match self {
+      Self::Next | Self::Forward(_) | Self::ForwardAll => true,
+      Self::Relative(count) => *count >= 0,
+      _ => false,
+    }
+  }
+}
+
+/* ========================================================================== *
+ * CURSOR                                                                     *
+ * ========================================================================== */
+
+/// A server-side cursor, iterating over the rows of a query in batches of
+/// [`PQCursorOptions::batch_size`] rows.
+///
+/// Cursors are declared within a transaction (see
+/// [`PQTransaction::cursor`][crate::transaction::PQTransaction::cursor])
+/// and borrow it, so that they can only be used until the end of it. Cursors
+/// declared `WITH HOLD` can also be declared outside of any transaction (see
+/// [`PQConnection::cursor`]). The cursor is closed when dropped.
+///
+/// See [`DECLARE`](https://www.postgresql.org/docs/current/sql-declare.html)
+///
+pub struct PQCursor<'a> {
+  id: usize,
+  connection: &'a PQConnection,
+  name: String,
+  options: PQCursorOptions,
+  rows: VecDeque<PQRow>,
+  exhausted: bool,
+  closed: bool,
+}
+
+debug_self!(PQCursor<'_>, id);
+
+impl Drop for PQCursor<'_> {
+  fn drop(&mut self) {
+    debug_drop!(self);
+    if self.closed { return }
+
+    if let Err(error) = self.finish_close() {
+      debug!("Error closing {:?}: {}", self, error);
+    }
+  }
+}
+
+impl <'a> PQCursor<'a> {
+  /// Declare a new cursor for the specified query.
+  ///
+  /// See [`DECLARE`](https://www.postgresql.org/docs/current/sql-declare.html)
+  ///
+  pub(crate) fn declare(connection: &'a PQConnection, query: &str, options: PQCursorOptions) -> PQResult<Self> {
+    if options.batch_size == 0 {
+      return Err("Cursor batch size must be greater than zero".into());
+    }
+
+    match connection.pq_transaction_status() {
+      PQTransactionStatus::InTransaction => (),
+      PQTransactionStatus::Idle if options.hold => (),
+      PQTransactionStatus::Idle => return Err("Cursors without hold must be declared in a transaction".into()),
+      status => return Err(format!("Unable to declare cursor (status={:?})", status).into()),
+    }
+
+    let id = debug_id();
+    let name = format!("pq_cursor_{}", id);
+    execute(connection, options.to_declare_command(&name, query))?;
+
+    Ok(debug_create!(Self {
+      id,
+      connection,
+      name,
+      options,
+      rows: VecDeque::new(),
+      exhausted: false,
+      closed: false,
+    }))
+  }
+
+  /// Return the name of this cursor.
+  ///
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Return the options this cursor was declared with.
+  ///
+  pub fn options(&self) -> PQCursorOptions {
+    self.options
+  }
+
+  /// Fetch rows from this cursor in the specified direction.
+  ///
+  /// This repositions the cursor, discarding any row buffered (but not yet
+  /// returned) while iterating: iteration then resumes from the new position.
+  ///
+  /// See [`FETCH`](https://www.postgresql.org/docs/current/sql-fetch.html)
+  ///
+  pub fn fetch(&mut self, direction: PQCursorDirection) -> PQResult<Vec<PQRow>> {
+    self.verify(direction)?;
+    self.rows.clear();
+    self.exhausted = false;
+
+    let command = format!("FETCH {} FROM {}", direction.as_sql(), self.name);
+    Ok(execute(self.connection, command)?.into())
+  }
+
+  /// Reposition this cursor without fetching any row, returning the number
+  /// of rows it moved over.
+  ///
+  /// This discards any row buffered (but not yet returned) while iterating.
+  ///
+  /// See [`MOVE`](https://www.postgresql.org/docs/current/sql-move.html)
+  ///
+  pub fn move_to(&mut self, direction: PQCursorDirection) -> PQResult<i32> {
+    self.verify(direction)?;
+    self.rows.clear();
+    self.exhausted = false;
+
+    let command = format!("MOVE {} IN {}", direction.as_sql(), self.name);
+    Ok(execute(self.connection, command)?.pq_cmd_tuples())
+  }
+
+  /// Close this cursor.
+  ///
+  /// See [`CLOSE`](https://www.postgresql.org/docs/current/sql-close.html)
+  ///
+  pub fn close(mut self) -> PQResult<()> {
+    self.finish_close()
+  }
+
+  /// Make sure this cursor is open and supports the specified direction.
+  ///
+  fn verify(&self, direction: PQCursorDirection) -> PQResult<()> {
+    match (self.closed, self.options.scroll || direction.is_forward()) {
+      (true, _) => Err(format!("Cursor {} already closed", self.name).into()),
+      (_, false) => Err(format!("Cursor {} can only scan forward (direction={:?})", self.name, direction).into()),
+      _ => Ok(()),
+    }
+  }
+
+  /// Fetch the next batch of rows while iterating.
+  ///
+  fn fetch_batch(&mut self) -> PQResult<()> {
+    let batch_size = self.options.batch_size;
+    let command = format!("FETCH FORWARD {} FROM {}", batch_size, self.name);
+    let rows: Vec<PQRow> = execute(self.connection, command)?.into();
+
+    self.exhausted = rows.len() < batch_size as usize;
+    self.rows.extend(rows);
+    Ok(())
+  }
+
+  /// Close this cursor (only once).
+  ///
+  fn finish_close(&mut self) -> PQResult<()> {
+    self.closed = true;
+    self.rows.clear();
+    execute(self.connection, format!("CLOSE {}", self.name)).map(|_| ())
+  }
+}
+
+impl Iterator for PQCursor<'_> {
+  type Item = PQResult<PQRow>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.rows.is_empty() && ! self.exhausted && ! self.closed {
+      if let Err(error) = self.fetch_batch() {
+        self.exhausted = true;
+        return Some(Err(error));
+      }
+    }
+
+    self.rows.pop_front().map(Ok)
+  }
+}
+
+/// Execute a command, returning an error if it failed.
+///
+fn execute(connection: &PQConnection, command: String) -> PQResult<PQResponse> {
+  connection.pq_exec(command)?.into_result()
+}
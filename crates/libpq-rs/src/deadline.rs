@@ -0,0 +1,160 @@
+//! Query execution bound to a deadline, canceling queries running too long.
+
+use crate::connection::PQConnection;
+use crate::connection::PQNextResult;
+use crate::debug::*;
+use crate::errors::*;
+use crate::response::PQResponse;
+use crate::response::PQResponseErrorField;
+use crate::rows::PQResultAssembler;
+use std::time::Duration;
+use std::time::Instant;
+
+/// SQLSTATE for `query_canceled`.
+pub static QUERY_CANCELED: &str = "57014";
+
+/// How long to wait for the server to acknowledge a cancel request.
+static CANCEL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// The outcome of driving a connection until a deadline.
+///
+enum Drive {
+  /// All results were received.
+  Done,
+  /// The deadline passed before all results were received.
+  Expired,
+  /// The results exceeded the connection's result size limit.
+  Exceeded(PQError),
+}
+
+/// Execute a command (with optional parameters) on a [`PQConnection`],
+/// collecting all its [`PQResponse`]s before the specified deadline.
+///
+/// The command is sent in nonblocking mode. Should the deadline pass, a
+/// cancel request is sent to the server, the connection is drained back to
+/// idle, and an error of kind [`PQErrorKind::Timeout`] is returned (unless
+/// the command completed anyway, in which case all its responses are).
+///
+/// If the server does not acknowledge the cancel request within a grace
+/// period, the connection is [reset][PQConnection::pq_reset] so that it can
+/// be used again (any error resetting it is returned instead).
+///
+/// The command is canceled in the same way should its results exceed the
+/// connection's [result size limit][PQConnection::set_result_size_limit],
+/// returning an error of kind [`PQErrorKind::ResultTooLarge`].
+///
+pub fn exec_with_deadline(
+  connection: &PQConnection,
+  command: String,
+  params: Option<Vec<String>>,
+  deadline: Instant,
+) -> PQResult<Vec<PQResponse>> {
+  let nonblocking = connection.pq_isnonblocking();
+  connection.pq_setnonblocking(true)?;
+
+  let result = execute(connection, command, params, deadline);
+
+  // Restore the original nonblocking mode, but don't hide any error
+  let restored = connection.pq_setnonblocking(nonblocking);
+  let responses = result?;
+  restored.map(|_| responses)
+}
+
+/// Send the command, collect its results, and cancel it if needed.
+///
+fn execute(
+  connection: &PQConnection,
+  command: String,
+  params: Option<Vec<String>>,
+  deadline: Instant,
+) -> PQResult<Vec<PQResponse>> {
+  match params {
+    Some(params) => connection.pq_send_query_params(command, params)?,
+    None => connection.pq_send_query(command)?,
+  }
+
+  let mut assembler = PQResultAssembler::start(connection)?;
+  let mut responses = Vec::<PQResponse>::new();
+
+  let exceeded = match drive(connection, deadline, &mut assembler, &mut responses)? {
+    Drive::Done => return Ok(responses),
+    Drive::Expired => {
+      debug!("Deadline expired, canceling query on {:?}", connection);
+      None
+    },
+    Drive::Exceeded(error) => {
+      debug!("Result size limit exceeded, canceling query on {:?}", connection);
+      Some(error)
+    },
+  };
+
+  connection.pq_get_cancel()?.pq_cancel()?;
+
+  // Drain whatever is left, most likely a "query_canceled" error
+  let grace = Instant::now() + CANCEL_GRACE_PERIOD;
+  let drained = loop {
+    match drive(connection, grace, &mut assembler, &mut responses)? {
+      Drive::Exceeded(_) => continue, // Already canceling, just keep draining
+      drained => break drained,
+    }
+  };
+
+  match (drained, exceeded) {
+    (Drive::Done, Some(error)) => Err(error),
+    (Drive::Done, None) => match responses.iter().any(canceled) {
+      true => Err(PQError::from("Query canceled after its deadline expired")
+        .with_kind(PQErrorKind::Timeout)),
+      // The command completed before the cancel request reached the server
+      false => Ok(responses),
+    },
+    (_, exceeded) => {
+      // The connection is still busy: reset it rather than leaving it unusable
+      debug!("Cancel request ignored, resetting {:?}", connection);
+      connection.pq_reset()?;
+      Err(exceeded.unwrap_or_else(|| {
+        PQError::from("Query canceled after its deadline expired, but the server did not respond (connection reset)")
+          .with_kind(PQErrorKind::Timeout)
+      }))
+    },
+  }
+}
+
+/// Flush our output and collect all results, until the deadline specified.
+///
+fn drive(
+  connection: &PQConnection,
+  deadline: Instant,
+  assembler: &mut PQResultAssembler,
+  responses: &mut Vec<PQResponse>,
+) -> PQResult<Drive> {
+  loop {
+    // Collect anything already available, only then check the deadline
+    let interest = match connection.try_get_result()? {
+      PQNextResult::Ready(response) => {
+        match assembler.push(response) {
+          Ok(response) => responses.extend(response),
+          Err(error) => return Ok(Drive::Exceeded(error)),
+        }
+        continue;
+      },
+      PQNextResult::Done => return Ok(Drive::Done),
+      PQNextResult::Wait(interest) => interest,
+    };
+
+    match deadline.checked_duration_since(Instant::now()) {
+      Some(remaining) => {
+        connection.poll(interest, Some(remaining))?;
+      },
+      None => return Ok(Drive::Expired),
+    }
+  }
+}
+
+/// Returns `true` if the specified response reports a canceled command.
+///
+fn canceled(response: &PQResponse) -> bool {
+  match response.pq_result_error_field(PQResponseErrorField::Sqlstate) {
+    Some(sqlstate) => sqlstate == QUERY_CANCELED,
+    None => false,
+  }
+}
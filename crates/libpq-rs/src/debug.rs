@@ -16,7 +16,10 @@ macro_rules! debug {
 ///
 #[cfg(not(debug_assertions))]
 macro_rules! debug {
-  ($($arg:tt)*) => {{}}
+  ($($arg:tt)*) => {{
+    // Never printed, but keeps arguments type-checked (and used)
+    if false { println!($($arg)*) }
+  }}
 }
 
 /// Emit a debug message when creating an instance
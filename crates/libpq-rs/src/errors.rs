@@ -1,12 +1,41 @@
 //! Errors stuff.
 
 use crate::connection::PQConnection;
+use crate::response::PQResponse;
+use crate::response::PQResponseErrorField;
+
+/// The kind of a [`PQError`], for errors requiring special handling.
+///
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub enum PQErrorKind {
+  /// Any error not falling in any other category.
+  Generic = 0,
+  /// An operation did not complete before its deadline.
+  Timeout = 1,
+  /// A result exceeded its memory budget, and its command was canceled.
+  ResultTooLarge = 2,
+  /// A connection violated its security policy.
+  SecurityPolicy = 3,
+}
 
 /// The root of all evil: any error thrown by LibPQ.
 ///
 #[derive(Debug, Clone)]
 pub struct PQError {
-  pub message: String
+  pub message: String,
+  /// The SQLSTATE code reported by the server, if any.
+  pub sqlstate: Option<String>,
+  /// The kind of this error.
+  pub kind: PQErrorKind,
+}
+
+impl PQError {
+  /// Return this [`PQError`] with the specified [`PQErrorKind`].
+  ///
+  pub fn with_kind(self, kind: PQErrorKind) -> Self {
+    Self { kind, ..self }
+  }
 }
 
 impl From<Option<String>> for PQError {
@@ -14,7 +43,7 @@ impl From<Option<String>> for PQError {
   ///
   fn from(message: Option<String>) -> Self {
     match message {
-      Some(message) => Self { message },
+      Some(message) => Self::from(message),
       None => Self::from("Unknown error".to_string()),
     }
   }
@@ -29,11 +58,23 @@ impl From<&PQConnection> for PQError {
   }
 }
 
+impl From<&PQResponse> for PQError {
+  /// Create a [`PQError`] from a [`PQResponse`]'s own
+  /// [error message][PQResponse::pq_result_error_message] and SQLSTATE code.
+  ///
+  fn from(value: &PQResponse) -> Self {
+    Self {
+      sqlstate: value.pq_result_error_field(PQResponseErrorField::Sqlstate),
+      ..Self::from(value.pq_result_error_message())
+    }
+  }
+}
+
 impl From<String> for PQError {
   /// Create a [`PQError`] from a [`String`].
   ///
   fn from(message: String) -> Self {
-      Self{ message }
+      Self{ message, sqlstate: None, kind: PQErrorKind::Generic }
   }
 }
 
@@ -41,7 +82,7 @@ impl From<&str> for PQError {
   /// Create a [`PQError`] from a [`str`]_ing_.
   ///
   fn from(message: &str) -> Self {
-      Self{ message: message.to_string() }
+      Self{ message: message.to_string(), sqlstate: None, kind: PQErrorKind::Generic }
   }
 }
 
@@ -0,0 +1,43 @@
+//! Safely quoted identifiers, for building dynamic SQL.
+
+use std::fmt::Display;
+
+/// An identifier (table, schema or channel name, ...) quoted for use in a
+/// SQL command.
+///
+/// This can only be created by [`PQConnection::pq_escape_identifier`][crate::connection::PQConnection::pq_escape_identifier],
+/// so that its quoting always matches the encoding of the live connection.
+///
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct PQIdentifier {
+  name: String,
+  quoted: String,
+}
+
+impl Display for PQIdentifier {
+  /// Format the _quoted_ identifier.
+  ///
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(&self.quoted)
+  }
+}
+
+impl PQIdentifier {
+  /// Create a new [`PQIdentifier`] from its name and escaped form.
+  ///
+  pub(crate) fn new(name: &str, quoted: String) -> Self {
+    Self { name: name.to_string(), quoted }
+  }
+
+  /// Return the original (unquoted) name of this identifier.
+  ///
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Return this identifier quoted for use in a SQL command.
+  ///
+  pub fn as_str(&self) -> &str {
+    &self.quoted
+  }
+}
@@ -4,6 +4,8 @@ use crate::errors::*;
 use std::ffi::CStr;
 use std::ffi::CString;
 use std::os::raw::c_char;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
 use std::ptr::null;
 
 /* ========================================================================== *
@@ -39,6 +41,13 @@ pub fn to_cstring(s: &str) -> CString {
   unsafe { CString::from_vec_unchecked(s.as_bytes().to_vec()) }
 }
 
+/// Converts a [`Path`] into a [`CString`], preserving its raw bytes.
+///
+pub fn path_to_cstring(path: &Path) -> PQResult<CString> {
+  CString::new(path.as_os_str().as_bytes())
+    .map_err(|_| format!("Path {:?} contains a NUL character", path).into())
+}
+
 /* ========================================================================== *
  * NULL TERMINATED ARRAY                                                      *
  * ========================================================================== */
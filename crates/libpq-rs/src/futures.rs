@@ -0,0 +1,317 @@
+//! Runtime-agnostic [`Future`]s and [`Stream`]s over nonblocking connections.
+
+use crate::connection::PQConnection;
+use crate::connection::PQNextResult;
+use crate::connection::PQPollingInterest;
+use crate::debug::*;
+use crate::errors::*;
+use crate::response::PQResponse;
+use crate::rows::PQResultAssembler;
+use futures_core::Stream;
+use polling::Event;
+use polling::Events;
+use polling::Poller;
+use std::collections::HashMap;
+use std::future::Future;
+use std::os::fd::BorrowedFd;
+use std::pin::Pin;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+
+/* ========================================================================== *
+ * WAKER REACTOR                                                              *
+ * ========================================================================== */
+
+/// A process-wide reactor, waiting on the sockets of pending [`Future`]s and
+/// [`Stream`]s in a background thread, and waking their tasks when ready.
+///
+struct PQWakerReactor {
+  poller: Poller,
+  wakers: Mutex<HashMap<usize, Waker>>,
+  next_key: AtomicUsize,
+}
+
+/// Our global [`PQWakerReactor`], started on first use.
+static REACTOR: OnceLock<Result<Arc<PQWakerReactor>, String>> = OnceLock::new();
+
+impl PQWakerReactor {
+  /// Return the global [`PQWakerReactor`], starting it if needed.
+  ///
+  fn get() -> PQResult<Arc<Self>> {
+    REACTOR.get_or_init(|| {
+      let poller = Poller::new()
+        .map_err(| err | format!("Error creating poller: {}", err))?;
+
+      let reactor = Arc::new(Self {
+        poller,
+        wakers: Mutex::new(HashMap::new()),
+        next_key: AtomicUsize::new(0),
+      });
+
+      let background = reactor.clone();
+      std::thread::Builder::new()
+        .name("libpq-rs-reactor".to_string())
+        .spawn(move || background.run())
+        .map_err(| err | format!("Error starting reactor thread: {}", err))?;
+
+      Ok(reactor)
+    }).clone().map_err(PQError::from)
+  }
+
+  /// Wait for events forever, waking the tasks interested in them.
+  ///
+  fn run(&self) {
+    let mut events = Events::new();
+
+    loop {
+      events.clear();
+      if let Err(error) = self.poller.wait(&mut events, None) {
+        debug!("Error waiting on poller: {}", error);
+        continue;
+      }
+
+      let mut wakers = self.wakers.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+      for event in events.iter() {
+        if let Some(waker) = wakers.remove(&event.key) {
+          waker.wake();
+        }
+      }
+    }
+  }
+}
+
+/// The registration of a connection's socket with our [`PQWakerReactor`].
+///
+struct PQRegistration {
+  reactor: Arc<PQWakerReactor>,
+  socket: i32,
+  key: usize,
+}
+
+impl PQRegistration {
+  /// Register the socket of the specified connection, without any interest.
+  ///
+  fn new(connection: &PQConnection) -> PQResult<Self> {
+    let reactor = PQWakerReactor::get()?;
+    let key = reactor.next_key.fetch_add(1, Ordering::Relaxed);
+    let socket = connection.pq_socket();
+
+    if socket < 0 {
+      return Err("Connection has no socket".into());
+    }
+
+    let registration = Self { reactor, socket, key };
+
+    unsafe {
+      registration.reactor.poller.add(&registration.source(), Event::none(key))
+        .map_err(| err | format!("Error adding to poller: {}", err))?;
+    }
+
+    Ok(registration)
+  }
+
+  /// Return our socket.
+  ///
+  fn source(&self) -> BorrowedFd<'_> {
+    unsafe { BorrowedFd::borrow_raw(self.socket) }
+  }
+
+  /// Arm our (oneshot) interest, waking the specified [`Waker`] when ready.
+  ///
+  fn arm(&self, interest: PQPollingInterest, waker: &Waker) -> PQResult<()> {
+    let event = match interest {
+      PQPollingInterest::Readable => Event::readable(self.key),
+      PQPollingInterest::Writable => Event::writable(self.key),
+    };
+
+    self.reactor.wakers.lock()
+      .unwrap_or_else(|poisoned| poisoned.into_inner())
+      .insert(self.key, waker.clone());
+
+    self.reactor.poller.modify(self.source(), event)
+      .map_err(| err | format!("Error modifying poller: {}", err).into())
+  }
+}
+
+impl Drop for PQRegistration {
+  fn drop(&mut self) {
+    self.reactor.wakers.lock()
+      .unwrap_or_else(|poisoned| poisoned.into_inner())
+      .remove(&self.key);
+    let _ = self.reactor.poller.delete(self.source());
+  }
+}
+
+/* ========================================================================== *
+ * RESPONSE STREAM                                                            *
+ * ========================================================================== */
+
+/// A [`Stream`] sending a command, and yielding each of its [`PQResponse`]s
+/// as they become available.
+///
+/// The connection is switched to nonblocking mode when the stream is first
+/// polled, and its socket is waited upon by a built-in background reactor,
+/// so this works with any executor.
+///
+/// When the connection has a [result size limit][PQConnection::set_result_size_limit]
+/// results are read one row at a time and reassembled: should the limit be
+/// exceeded, the command is canceled, and once its remaining results are
+/// discarded an error of kind [`PQErrorKind::ResultTooLarge`] is yielded.
+///
+/// Dropping the stream before it ends leaves the connection busy: further
+/// results must be drained (or the command canceled) before reusing it.
+///
+pub struct PQResponseStream<'a> {
+  id: usize,
+  connection: &'a PQConnection,
+  command: Option<(String, Option<Vec<String>>)>,
+  registration: Option<PQRegistration>,
+  assembler: Option<PQResultAssembler>,
+  exceeded: Option<PQError>,
+  finished: bool,
+}
+
+debug_self!(PQResponseStream<'_>, id);
+
+impl Drop for PQResponseStream<'_> {
+  fn drop(&mut self) {
+    debug_drop!(self);
+  }
+}
+
+impl <'a> PQResponseStream<'a> {
+  /// Create a new [`PQResponseStream`] for the specified command and
+  /// (optional) parameters.
+  ///
+  pub fn new(connection: &'a PQConnection, command: String, params: Option<Vec<String>>) -> Self {
+    debug_create!(Self {
+      id: debug_id(),
+      connection,
+      command: Some((command, params)),
+      registration: None,
+      assembler: None,
+      exceeded: None,
+      finished: false,
+    })
+  }
+
+  /// Send our command (only once) and register our connection's socket.
+  ///
+  fn start(&mut self) -> PQResult<()> {
+    if let Some((command, params)) = self.command.take() {
+      self.connection.pq_setnonblocking(true)?;
+
+      match params {
+        Some(params) => self.connection.pq_send_query_params(command, params)?,
+        None => self.connection.pq_send_query(command)?,
+      }
+
+      self.assembler = Some(PQResultAssembler::start(self.connection)?);
+      self.registration = Some(PQRegistration::new(self.connection)?);
+    }
+
+    Ok(())
+  }
+
+  /// Try to get the next response, arming our waker if none is available.
+  ///
+  fn next_response(&mut self, waker: &Waker) -> PQResult<Poll<Option<PQResponse>>> {
+    self.start()?;
+
+    let (Some(assembler), Some(registration)) = (&mut self.assembler, &self.registration) else {
+      return Err("Stream polled before being started".into());
+    };
+
+    loop {
+      match self.connection.try_get_result()? {
+        PQNextResult::Ready(response) => match assembler.push(response) {
+          Ok(Some(response)) if self.exceeded.is_none() => return Ok(Poll::Ready(Some(response))),
+          // Rows still being reassembled, or results discarded after a cancel
+          Ok(_) => continue,
+          Err(error) => {
+            debug!("Result size limit exceeded, canceling query on {:?}", self.connection);
+            self.connection.pq_get_cancel()?.pq_cancel()?;
+            self.exceeded = Some(error);
+          },
+        },
+        PQNextResult::Done => return match self.exceeded.take() {
+          Some(error) => Err(error),
+          None => Ok(Poll::Ready(None)),
+        },
+        PQNextResult::Wait(interest) => return registration.arm(interest, waker).map(|_| Poll::Pending),
+      }
+    }
+  }
+}
+
+impl Stream for PQResponseStream<'_> {
+  type Item = PQResult<PQResponse>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let this = self.get_mut();
+
+    if this.finished {
+      return Poll::Ready(None);
+    }
+
+    match this.next_response(cx.waker()) {
+      Ok(Poll::Pending) => Poll::Pending,
+      Ok(Poll::Ready(Some(response))) => Poll::Ready(Some(Ok(response))),
+      Ok(Poll::Ready(None)) => {
+        this.finished = true;
+        this.registration = None;
+        Poll::Ready(None)
+      },
+      Err(error) => {
+        this.finished = true;
+        this.registration = None;
+        Poll::Ready(Some(Err(error)))
+      },
+    }
+  }
+}
+
+/* ========================================================================== *
+ * QUERY FUTURE                                                               *
+ * ========================================================================== */
+
+/// A [`Future`] sending a command, and resolving to all its [`PQResponse`]s.
+///
+/// See [`PQResponseStream`] for details.
+///
+pub struct PQQueryFuture<'a> {
+  stream: PQResponseStream<'a>,
+  responses: Vec<PQResponse>,
+}
+
+impl <'a> PQQueryFuture<'a> {
+  /// Create a new [`PQQueryFuture`] for the specified command and
+  /// (optional) parameters.
+  ///
+  pub fn new(connection: &'a PQConnection, command: String, params: Option<Vec<String>>) -> Self {
+    Self { stream: PQResponseStream::new(connection, command, params), responses: Vec::new() }
+  }
+}
+
+impl Future for PQQueryFuture<'_> {
+  type Output = PQResult<Vec<PQResponse>>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.get_mut();
+
+    loop {
+      match Pin::new(&mut this.stream).poll_next(cx) {
+        Poll::Pending => return Poll::Pending,
+        Poll::Ready(Some(Ok(response))) => this.responses.push(response),
+        Poll::Ready(Some(Err(error))) => return Poll::Ready(Err(error)),
+        Poll::Ready(None) => return Poll::Ready(Ok(std::mem::take(&mut this.responses))),
+      }
+    }
+  }
+}
@@ -0,0 +1,74 @@
+//! Snapshots of where (and how) a live connection is connected to.
+
+use crate::connection::PQConnection;
+
+/// A snapshot of the properties of a live [`PQConnection`].
+///
+/// With multi-host connection strings, `host`, `hostaddr` and `port` are the
+/// ones of the server actually connected to.
+///
+/// See [Connection Status Functions](https://www.postgresql.org/docs/current/libpq-status.html)
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PQConnectionInfo {
+  /// The server host name, IP address or socket directory.
+  pub host: Option<String>,
+  /// The server IP address (if connected over TCP/IP).
+  pub hostaddr: Option<String>,
+  /// The server port.
+  pub port: Option<String>,
+  /// The database name.
+  pub dbname: Option<String>,
+  /// The user name.
+  pub user: Option<String>,
+  /// The command-line options passed in the connection request.
+  pub options: Option<String>,
+  /// The server version.
+  pub server_version: Option<String>,
+  /// The frontend/backend protocol version.
+  pub protocol_version: i32,
+  /// The process ID of the backend handling the connection.
+  pub backend_pid: i32,
+  /// Whether the connection uses SSL.
+  pub ssl_in_use: bool,
+  /// Whether authentication required a password, but none was available.
+  pub needs_password: bool,
+  /// Whether authentication used a password.
+  pub used_password: bool,
+  /// Whether authentication used GSSAPI.
+  pub used_gssapi: bool,
+  /// The values of all [parameters reported by the server][crate::session::REPORTED_PARAMETERS].
+  pub parameters: Vec<(String, Option<String>)>,
+}
+
+impl From<&PQConnection> for PQConnectionInfo {
+  fn from(connection: &PQConnection) -> Self {
+    Self {
+      host: connection.pq_host(),
+      hostaddr: connection.pq_hostaddr(),
+      port: connection.pq_port(),
+      dbname: connection.pq_db(),
+      user: connection.pq_user(),
+      options: connection.pq_options(),
+      server_version: connection.pq_server_version(),
+      protocol_version: connection.pq_protocol_version(),
+      backend_pid: connection.pq_backend_pid(),
+      ssl_in_use: connection.pq_ssl_in_use(),
+      needs_password: connection.pq_connection_needs_password(),
+      used_password: connection.pq_connection_used_password(),
+      used_gssapi: connection.pq_connection_used_gssapi(),
+      parameters: connection.parameter_statuses(),
+    }
+  }
+}
+
+impl PQConnectionInfo {
+  /// Return the value of a parameter reported by the server, if any.
+  ///
+  pub fn parameter(&self, name: &str) -> Option<&str> {
+    self.parameters
+      .iter()
+      .find(|(key, _)| key == name)
+      .and_then(|(_, value)| value.as_deref())
+  }
+}
@@ -0,0 +1,198 @@
+//! Large objects, read and written like files.
+
+use crate::connection::PQConnection;
+use crate::debug::*;
+use crate::errors::*;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+
+/// Flag opening a large object for reading (`INV_READ`).
+static INV_READ: i32 = 0x00040000;
+/// Flag opening a large object for writing (`INV_WRITE`).
+static INV_WRITE: i32 = 0x00020000;
+
+/// Seek relative to the start of a large object (`SEEK_SET`).
+static SEEK_SET: i32 = 0;
+/// Seek relative to the current position (`SEEK_CUR`).
+static SEEK_CUR: i32 = 1;
+/// Seek relative to the end of a large object (`SEEK_END`).
+static SEEK_END: i32 = 2;
+
+/// The mode a [`PQLargeObject`] is opened with.
+///
+/// See [`lo_open`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-OPEN)
+///
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub enum PQLargeObjectMode {
+  /// Read only, seeing the contents as of the transaction snapshot.
+  Read,
+  /// Write only.
+  Write,
+  /// Read and write, seeing the contents as currently written.
+  ReadWrite,
+}
+
+impl PQLargeObjectMode {
+  /// Returns the `INV_READ` / `INV_WRITE` flags for this mode.
+  ///
+  pub fn as_flags(&self) -> i32 {
+    match self {
+      Self::Read => INV_READ,
+      Self::Write => INV_WRITE,
+      Self::ReadWrite => INV_READ | INV_WRITE,
+    }
+  }
+}
+
+/// An open large object, implementing [`Read`], [`Write`] and [`Seek`].
+///
+/// Large objects can only be opened within a transaction (see
+/// [`PQTransaction::lo_open`][crate::transaction::PQTransaction::lo_open])
+/// and borrow it, as their descriptors are closed automatically by the server
+/// when the transaction ends. The descriptor is also closed when this is
+/// dropped within its transaction.
+///
+/// See [Large Objects](https://www.postgresql.org/docs/current/largeobjects.html)
+///
+pub struct PQLargeObject<'a> {
+  id: usize,
+  connection: &'a PQConnection,
+  oid: u32,
+  fd: i32,
+  closed: bool,
+}
+
+debug_self!(PQLargeObject<'_>, id);
+
+impl Drop for PQLargeObject<'_> {
+  fn drop(&mut self) {
+    debug_drop!(self);
+
+    // Descriptors are closed by the server when the transaction ends
+    if self.closed || self.connection.lo_verify().is_err() { return }
+
+    if let Err(error) = self.finish_close() {
+      debug!("Error closing {:?}: {}", self, error);
+    }
+  }
+}
+
+impl <'a> PQLargeObject<'a> {
+  /// Open the large object with the specified OID.
+  ///
+  /// See [`lo_open`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-OPEN)
+  ///
+  pub(crate) fn open(connection: &'a PQConnection, oid: u32, mode: PQLargeObjectMode) -> PQResult<Self> {
+    let fd = connection.lo_open_descriptor(oid, mode.as_flags())?;
+
+    Ok(debug_create!(Self {
+      id: debug_id(),
+      connection,
+      oid,
+      fd,
+      closed: false,
+    }))
+  }
+
+  /// Return the OID of this large object.
+  ///
+  pub fn oid(&self) -> u32 {
+    self.oid
+  }
+
+  /// Return the current position within this large object.
+  ///
+  /// See [`lo_tell64`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-TELL)
+  ///
+  pub fn tell(&self) -> PQResult<u64> {
+    self.verify()?;
+    self.connection.lo_tell64(self.fd).map(|position| position as u64)
+  }
+
+  /// Truncate (or extend with zeroes) this large object to the specified
+  /// length.
+  ///
+  /// See [`lo_truncate64`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-TRUNCATE)
+  ///
+  pub fn truncate(&mut self, length: u64) -> PQResult<()> {
+    self.verify()?;
+    let length = i64::try_from(length)
+      .map_err(|_| format!("Large object length {} too large", length))?;
+    self.connection.lo_truncate64(self.fd, length)
+  }
+
+  /// Close this large object.
+  ///
+  /// See [`lo_close`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-CLOSE)
+  ///
+  pub fn close(mut self) -> PQResult<()> {
+    self.verify()?;
+    self.finish_close()
+  }
+
+  /// Make sure this large object is still open, and its transaction alive.
+  ///
+  fn verify(&self) -> PQResult<()> {
+    match self.closed {
+      true => Err(format!("Large object {} already closed", self.oid).into()),
+      false => self.connection.lo_verify(),
+    }
+  }
+
+  /// Close this large object (only once).
+  ///
+  fn finish_close(&mut self) -> PQResult<()> {
+    self.closed = true;
+    self.connection.lo_close(self.fd)
+  }
+}
+
+impl Read for PQLargeObject<'_> {
+  /// See [`lo_read`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-READ)
+  ///
+  fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+    self.verify()
+      .and_then(|_| self.connection.lo_read(self.fd, buffer))
+      .map_err(std::io::Error::other)
+  }
+}
+
+impl Write for PQLargeObject<'_> {
+  /// See [`lo_write`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-WRITE)
+  ///
+  fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+    self.verify()
+      .and_then(|_| self.connection.lo_write(self.fd, buffer))
+      .map_err(std::io::Error::other)
+  }
+
+  /// Writes go straight to the server: there is nothing to flush.
+  ///
+  fn flush(&mut self) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+impl Seek for PQLargeObject<'_> {
+  /// See [`lo_lseek64`](https://www.postgresql.org/docs/current/lo-interfaces.html#LO-SEEK)
+  ///
+  fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+    let (offset, whence) = match position {
+      SeekFrom::Start(offset) => match i64::try_from(offset) {
+        Ok(offset) => (offset, SEEK_SET),
+        Err(_) => return Err(std::io::Error::new(
+          std::io::ErrorKind::InvalidInput,
+          format!("Large object offset {} too large", offset))),
+      },
+      SeekFrom::Current(offset) => (offset, SEEK_CUR),
+      SeekFrom::End(offset) => (offset, SEEK_END),
+    };
+
+    self.verify()
+      .and_then(|_| self.connection.lo_lseek64(self.fd, offset, whence))
+      .map(|position| position as u64)
+      .map_err(std::io::Error::other)
+  }
+}
@@ -4,14 +4,37 @@ use napi_ts::*;
 
 use ffi::to_string_lossy;
 use std::error::Error;
+pub mod cancel;
+pub mod certificate;
+pub mod config;
 pub mod connection;
 pub mod conninfo;
+pub mod credentials;
+pub mod cursor;
+pub mod deadline;
 pub mod debug;
 pub mod errors;
+pub mod escape;
 pub mod ffi;
+pub mod futures;
+pub mod introspection;
+pub mod large_object;
+pub mod listener;
 pub mod notices;
 pub mod notifications;
+pub mod password;
+pub mod pgpass;
+pub mod pool;
+pub mod reactor;
 pub mod response;
+pub mod retry;
+pub mod rows;
+pub mod security;
+pub mod session;
+#[cfg(feature = "tokio")]
+pub mod tokio_fd;
+pub mod transaction;
+pub mod twophase;
 
 /* ========================================================================== */
 
@@ -0,0 +1,197 @@
+//! `LISTEN` / `NOTIFY` subscriptions surviving reconnections.
+
+use crate::connection::PQConnection;
+use crate::connection::PQConnectionStatus;
+use crate::connection::PQPollingInterest;
+use crate::conninfo::PQConninfo;
+use crate::debug::*;
+use crate::errors::*;
+use crate::notifications::PQNotification;
+use std::collections::BTreeSet;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+/// An event emitted by a [`PQListener`].
+///
+#[derive(Debug)]
+pub enum PQListenerEvent {
+  /// A notification was received on one of the channels listened to.
+  Notification(PQNotification),
+  /// The connection was lost and re-established: any notification sent while
+  /// disconnected was lost, and consumers should resynchronize their state.
+  PossibleGap,
+}
+
+/// A manager for a set of `LISTEN` channels on a dedicated [`PQConnection`].
+///
+/// When the connection is lost, the listener resets it and subscribes again
+/// to all its channels, emitting a [`PQListenerEvent::PossibleGap`] event.
+///
+/// See [Asynchronous Notification](https://www.postgresql.org/docs/current/libpq-notify.html)
+///
+pub struct PQListener {
+  id: usize,
+  connection: PQConnection,
+  channels: BTreeSet<String>,
+  events: VecDeque<PQListenerEvent>,
+  resubscribe: bool,
+}
+
+debug_self!(PQListener, id);
+
+impl TryFrom<PQConninfo> for PQListener {
+  type Error = PQError;
+
+  /// Open a new connection dedicated to a [`PQListener`].
+  ///
+  fn try_from(info: PQConninfo) -> PQResult<Self> {
+    Ok(Self::from(PQConnection::try_from(info)?))
+  }
+}
+
+impl From<PQConnection> for PQListener {
+  /// Create a [`PQListener`] taking ownership of a [`PQConnection`].
+  ///
+  fn from(connection: PQConnection) -> Self {
+    debug_create!(Self {
+      id: debug_id(),
+      connection,
+      channels: BTreeSet::new(),
+      events: VecDeque::new(),
+      resubscribe: false,
+    })
+  }
+}
+
+impl Drop for PQListener {
+  fn drop(&mut self) {
+    debug_drop!(self);
+  }
+}
+
+impl PQListener {
+  /// Return the [`PQConnection`] used by this listener.
+  ///
+  pub fn connection(&self) -> &PQConnection {
+    &self.connection
+  }
+
+  /// Return the channels this listener is subscribed to.
+  ///
+  pub fn channels(&self) -> impl Iterator<Item = &String> {
+    self.channels.iter()
+  }
+
+  /// Subscribe to the specified channel.
+  ///
+  /// See [`LISTEN`](https://www.postgresql.org/docs/current/sql-listen.html)
+  ///
+  pub fn listen(&mut self, channel: &str) -> PQResult<()> {
+    let command = format!("LISTEN {}", self.connection.pq_escape_identifier(channel)?);
+    self.connection.pq_exec(command)?.into_result()?;
+    self.channels.insert(channel.to_string());
+    Ok(())
+  }
+
+  /// Unsubscribe from the specified channel.
+  ///
+  /// See [`UNLISTEN`](https://www.postgresql.org/docs/current/sql-unlisten.html)
+  ///
+  pub fn unlisten(&mut self, channel: &str) -> PQResult<()> {
+    let command = format!("UNLISTEN {}", self.connection.pq_escape_identifier(channel)?);
+    self.connection.pq_exec(command)?.into_result()?;
+    self.channels.remove(channel);
+    Ok(())
+  }
+
+  /// Unsubscribe from all channels.
+  ///
+  /// See [`UNLISTEN`](https://www.postgresql.org/docs/current/sql-unlisten.html)
+  ///
+  pub fn unlisten_all(&mut self) -> PQResult<()> {
+    self.connection.pq_exec("UNLISTEN *".to_string())?.into_result()?;
+    self.channels.clear();
+    Ok(())
+  }
+
+  /// Reset the connection and subscribe again to all channels, queueing a
+  /// [`PQListenerEvent::PossibleGap`] event.
+  ///
+  /// The event is queued as soon as the connection is reset: should any
+  /// subscription then fail, the listener keeps trying to subscribe again
+  /// on each [wait][PQListener::wait] until all subscriptions succeed.
+  ///
+  pub fn reconnect(&mut self) -> PQResult<()> {
+    debug!("Reconnecting {:?}", self);
+    self.connection.pq_reset()?;
+
+    self.events.push_back(PQListenerEvent::PossibleGap);
+    self.resubscribe = true;
+    self.resubscribe()
+  }
+
+  /// Subscribe again to all channels after a reset, if needed.
+  ///
+  fn resubscribe(&mut self) -> PQResult<()> {
+    if ! self.resubscribe { return Ok(()) }
+
+    for channel in self.channels.iter() {
+      let command = format!("LISTEN {}", self.connection.pq_escape_identifier(channel)?);
+      self.connection.pq_exec(command)?.into_result()?;
+    }
+
+    self.resubscribe = false;
+    Ok(())
+  }
+
+  /// Wait for the next event, up to the specified timeout (or forever if no
+  /// timeout was specified), returning `None` if the timeout expired.
+  ///
+  /// When the connection is lost this will attempt to [reconnect][PQListener::reconnect],
+  /// and return any error encountered while doing so (or while subscribing
+  /// again to all channels afterwards).
+  ///
+  pub fn wait(&mut self, timeout: Option<Duration>) -> PQResult<Option<PQListenerEvent>> {
+    // Timeouts too large to be represented simply mean "forever"
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+
+    loop {
+      if let Some(event) = self.events.pop_front() {
+        return Ok(Some(event));
+      }
+
+      // Read anything available, reconnecting if the connection was lost
+      if self.connection.pq_consume_input().is_err() ||
+         self.connection.pq_status() == PQConnectionStatus::Bad {
+        self.reconnect()?;
+        continue;
+      }
+
+      // Retry any subscription that failed after the last reconnection
+      self.resubscribe()?;
+
+      let notifications = self.connection.pq_notifies()?;
+      if ! notifications.is_empty() {
+        self.events.extend(notifications.into_iter().map(PQListenerEvent::Notification));
+        continue;
+      }
+
+      let remaining = match deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+          Some(remaining) => Some(remaining),
+          None => return Ok(None),
+        },
+        None => None,
+      };
+
+      if let Err(error) = self.connection.poll(PQPollingInterest::Readable, remaining) {
+        // Let the next iteration reconnect if the connection was lost
+        if self.connection.pq_consume_input().is_ok() &&
+           self.connection.pq_status() == PQConnectionStatus::Ok {
+          return Err(error);
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,239 @@
+//! Client-side password hashing, so that cleartext passwords never reach the
+//! server.
+
+use crate::errors::*;
+use openssl_sys::*;
+use std::os::raw::c_char;
+use std::os::raw::c_int;
+use std::os::raw::c_uint;
+use std::os::raw::c_void;
+use std::ptr::null_mut;
+
+/// The default number of SCRAM iterations (PostgreSQL's `scram_iterations`).
+pub static SCRAM_ITERATIONS: u32 = 4096;
+/// The length (in bytes) of randomly generated SCRAM salts.
+static SCRAM_SALT_LENGTH: usize = 16;
+
+/// The algorithm used to hash a password.
+///
+/// See [`PQencryptPasswordConn`](https://www.postgresql.org/docs/current/libpq-misc.html#LIBPQ-PQENCRYPTPASSWORDCONN)
+///
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub enum PQPasswordAlgorithm {
+  /// SCRAM with SHA-256 (`scram-sha-256`).
+  ScramSha256,
+  /// MD5 (`md5`), deprecated since PostgreSQL 18.
+  Md5,
+}
+
+impl PQPasswordAlgorithm {
+  /// Returns the name of this algorithm, as understood by LibPQ.
+  ///
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::ScramSha256 => "scram-sha-256",
+      Self::Md5 => "md5",
+    }
+  }
+}
+
+/* ========================================================================== *
+ * SCRAM-SHA-256                                                              *
+ * ========================================================================== */
+
+/// Generate a SCRAM-SHA-256 verifier for the specified password, with a
+/// random salt and the default number of iterations, without any connection
+/// to the server.
+///
+/// The result can be used verbatim in `CREATE ROLE ... PASSWORD '...'`, and
+/// is equivalent to the one generated by [`PQConnection::pq_encrypt_password_conn`][crate::connection::PQConnection::pq_encrypt_password_conn].
+///
+/// See [`scram_sha_256_verifier_with`]
+///
+pub fn scram_sha_256_verifier(password: &str) -> PQResult<String> {
+  let salt = random_bytes(SCRAM_SALT_LENGTH)?;
+  scram_sha_256_verifier_with(password, &salt, SCRAM_ITERATIONS)
+}
+
+/// Generate a SCRAM-SHA-256 verifier for the specified password, salt and
+/// number of iterations, in the `SCRAM-SHA-256$<iterations>:<salt>$<StoredKey>:<ServerKey>`
+/// format stored by PostgreSQL.
+///
+/// PostgreSQL normalizes passwords with SASLprep, which only alters non-ASCII
+/// characters: as this is not implemented here, non-ASCII passwords are
+/// rejected (use [`PQConnection::pq_encrypt_password_conn`][crate::connection::PQConnection::pq_encrypt_password_conn]
+/// for those).
+///
+/// See [RFC 5802](https://datatracker.ietf.org/doc/html/rfc5802#section-3)
+/// See [RFC 7677](https://datatracker.ietf.org/doc/html/rfc7677)
+///
+pub fn scram_sha_256_verifier_with(password: &str, salt: &[u8], iterations: u32) -> PQResult<String> {
+  if ! password.is_ascii() {
+    return Err("Unable to generate SCRAM verifiers for non-ASCII passwords".into());
+  } else if salt.is_empty() {
+    return Err("SCRAM salt must not be empty".into());
+  } else if iterations == 0 {
+    return Err("SCRAM iterations must be greater than zero".into());
+  }
+
+  let salted_password = pbkdf2_hmac_sha256(password.as_bytes(), salt, iterations)?;
+  let client_key = hmac_sha256(&salted_password, b"Client Key")?;
+  let stored_key = sha256(&client_key)?;
+  let server_key = hmac_sha256(&salted_password, b"Server Key")?;
+
+  Ok(format!("SCRAM-SHA-256${}:{}${}:{}",
+    iterations,
+    base64(salt),
+    base64(&stored_key),
+    base64(&server_key)))
+}
+
+/* ========================================================================== *
+ * PRIMITIVES                                                                 *
+ * ========================================================================== */
+
+/// Generate the specified number of cryptographically secure random bytes.
+///
+/// See [`RAND_bytes`](https://docs.openssl.org/master/man3/RAND_bytes/)
+///
+fn random_bytes(length: usize) -> PQResult<Vec<u8>> {
+  let mut bytes = vec![0u8; length];
+
+  match unsafe { RAND_bytes(bytes.as_mut_ptr(), length as c_int) } {
+    1 => Ok(bytes),
+    _ => Err("Unable to generate random bytes".into()),
+  }
+}
+
+/// Compute the SHA-256 digest of the specified data.
+///
+/// See [`SHA256`](https://docs.openssl.org/master/man3/SHA256_Init/)
+///
+fn sha256(data: &[u8]) -> PQResult<[u8; 32]> {
+  let mut digest = [0u8; 32];
+
+  match unsafe { SHA256(data.as_ptr(), data.len(), digest.as_mut_ptr()) }.is_null() {
+    false => Ok(digest),
+    true => Err("Unable to compute SHA-256 digest".into()),
+  }
+}
+
+/// Compute the HMAC-SHA-256 of the specified data.
+///
+/// See [`HMAC`](https://docs.openssl.org/master/man3/HMAC/)
+///
+fn hmac_sha256(key: &[u8], data: &[u8]) -> PQResult<[u8; 32]> {
+  let mut digest = [0u8; 32];
+  let mut length = digest.len() as c_uint;
+
+  unsafe {
+    let context = HMAC_CTX_new();
+    if context.is_null() {
+      return Err("Unable to create HMAC context".into());
+    }
+
+    let success =
+      HMAC_Init_ex(context, key.as_ptr() as *const c_void, key.len() as c_int, EVP_sha256(), null_mut()) == 1 &&
+      HMAC_Update(context, data.as_ptr(), data.len()) == 1 &&
+      HMAC_Final(context, digest.as_mut_ptr(), &mut length) == 1;
+
+    HMAC_CTX_free(context);
+
+    match success {
+      true => Ok(digest),
+      false => Err("Unable to compute HMAC-SHA-256".into()),
+    }
+  }
+}
+
+/// Derive a single 32-byte key with PBKDF2-HMAC-SHA-256 (SCRAM's `Hi()`).
+///
+/// See [`PKCS5_PBKDF2_HMAC`](https://docs.openssl.org/master/man3/PKCS5_PBKDF2_HMAC/)
+///
+fn pbkdf2_hmac_sha256(password: &[u8], salt: &[u8], iterations: u32) -> PQResult<[u8; 32]> {
+  let mut key = [0u8; 32];
+  let iterations = c_int::try_from(iterations)
+    .map_err(|_| format!("Too many SCRAM iterations ({})", iterations))?;
+
+  match unsafe {
+    PKCS5_PBKDF2_HMAC(
+      password.as_ptr() as *const c_char,
+      password.len() as c_int,
+      salt.as_ptr(),
+      salt.len() as c_int,
+      iterations,
+      EVP_sha256(),
+      key.len() as c_int,
+      key.as_mut_ptr(),
+    )
+  } {
+    1 => Ok(key),
+    _ => Err("Unable to derive PBKDF2-HMAC-SHA-256 key".into()),
+  }
+}
+
+/// Encode the specified data in (padded) standard Base64.
+///
+/// See [`EVP_EncodeBlock`](https://docs.openssl.org/master/man3/EVP_EncodeInit/)
+///
+fn base64(data: &[u8]) -> String {
+  // Four characters for every three bytes, plus a terminating NUL
+  let mut encoded = vec![0u8; data.len().div_ceil(3) * 4 + 1];
+  let length = unsafe { EVP_EncodeBlock(encoded.as_mut_ptr(), data.as_ptr(), data.len() as c_int) };
+
+  encoded.truncate(length.max(0) as usize);
+  String::from_utf8_lossy(&encoded).to_string()
+}
+
+/* ========================================================================== *
+ * TESTS                                                                      *
+ * ========================================================================== */
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+  }
+
+  #[test]
+  fn primitives() {
+    // FIPS 180-2, appendix B.1
+    assert_eq!(hex(&sha256(b"abc").unwrap()),
+      "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    // RFC 4231, test case 2
+    assert_eq!(hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?").unwrap()),
+      "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    // RFC 7914, section 11 (first 32 bytes)
+    assert_eq!(hex(&pbkdf2_hmac_sha256(b"passwd", b"salt", 1).unwrap()),
+      "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc");
+    // RFC 4648, section 10
+    assert_eq!(base64(b""), "");
+    assert_eq!(base64(b"fo"), "Zm8=");
+    assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+  }
+
+  #[test]
+  fn scram_sha_256_verifiers() {
+    // The salt and password of RFC 7677, section 3
+    let salt = [
+      0x5b, 0x6d, 0x99, 0x68, 0x9d, 0x12, 0x35, 0x8e, 0xec, 0xa0, 0x4b, 0x14, 0x12, 0x36, 0xfa, 0x81,
+    ];
+
+    assert_eq!(scram_sha_256_verifier_with("pencil", &salt, 4096).unwrap(),
+      "SCRAM-SHA-256$4096:W22ZaJ0SNY7soEsUEjb6gQ==$WG5d8oPm3OtcPnkdi4Uo7BkeZkBFzpcXkuLmtbsT4qY=:wfPLwcE6nTWhTAmQ7tl2KeoiWGPlZqQxSrmfPwDl2dU=");
+
+    assert!(scram_sha_256_verifier_with("pässword", &salt, 4096).is_err());
+    assert!(scram_sha_256_verifier_with("pencil", &[], 4096).is_err());
+    assert!(scram_sha_256_verifier_with("pencil", &salt, 0).is_err());
+  }
+
+  #[test]
+  fn random_salts() {
+    let first = scram_sha_256_verifier("pencil").unwrap();
+    let second = scram_sha_256_verifier("pencil").unwrap();
+    assert!(first.starts_with("SCRAM-SHA-256$4096:"));
+    assert_ne!(first, second);
+  }
+}
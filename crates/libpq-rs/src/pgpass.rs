@@ -0,0 +1,479 @@
+//! Read, query and edit password files (`.pgpass`) like LibPQ does.
+
+use crate::config::home_file;
+use crate::conninfo::PQConninfo;
+use crate::debug::*;
+use crate::errors::*;
+use std::fmt::Debug;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// The host matched by `localhost` entries, when no host is specified.
+static DEFAULT_HOST: &str = "localhost";
+/// The socket directory compiled in the bundled LibPQ (`DEFAULT_PGSOCKET_DIR`).
+static DEFAULT_SOCKET_DIR: &str = "/tmp";
+/// The port matched when no port is specified (`DEF_PGPORT_STR`).
+static DEFAULT_PORT: &str = "5432";
+/// What to display in place of passwords.
+static REDACTED: &str = "********";
+
+/* ========================================================================== *
+ * ENTRIES                                                                    *
+ * ========================================================================== */
+
+/// A single `hostname:port:database:username:password` entry of a password
+/// file, where `None` fields are the `*` wildcard matching anything.
+///
+/// See [The Password File](https://www.postgresql.org/docs/current/libpq-pgpass.html)
+///
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub struct PQPassEntry {
+  /// The host name (or `None` for any host).
+  pub hostname: Option<String>,
+  /// The port (or `None` for any port).
+  pub port: Option<String>,
+  /// The database name (or `None` for any database).
+  pub database: Option<String>,
+  /// The user name (or `None` for any user).
+  pub username: Option<String>,
+  /// The password.
+  pub password: String,
+}
+
+impl Debug for PQPassEntry {
+  /// Format this [`PQPassEntry`] with its password redacted.
+  ///
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("PQPassEntry")
+      .field("hostname", &self.hostname)
+      .field("port", &self.port)
+      .field("database", &self.database)
+      .field("username", &self.username)
+      .field("password", &REDACTED)
+      .finish()
+  }
+}
+
+impl PQPassEntry {
+  /// Create a new [`PQPassEntry`], where `"*"` fields are wildcards.
+  ///
+  pub fn new(hostname: &str, port: &str, database: &str, username: &str, password: &str) -> Self {
+    let field = |value: &str| match value {
+      "*" => None,
+      value => Some(value.to_string()),
+    };
+
+    Self {
+      hostname: field(hostname),
+      port: field(port),
+      database: field(database),
+      username: field(username),
+      password: password.to_string(),
+    }
+  }
+
+  /// Check whether this entry matches the specified host, port, database and
+  /// user names, comparing them _literally_ (no defaults are applied).
+  ///
+  pub fn matches(&self, hostname: &str, port: &str, database: &str, username: &str) -> bool {
+    let matches = |field: &Option<String>, value: &str| match field {
+      Some(field) => field == value,
+      None => true,
+    };
+
+    matches(&self.hostname, hostname)
+      && matches(&self.port, port)
+      && matches(&self.database, database)
+      && matches(&self.username, username)
+  }
+
+  /// Check whether this entry has exactly the same (non-password) fields as
+  /// another one.
+  ///
+  fn same_key(&self, other: &Self) -> bool {
+    self.hostname == other.hostname
+      && self.port == other.port
+      && self.database == other.database
+      && self.username == other.username
+  }
+
+  /// Render this entry as a line of a password file, escaping `:` and `\`
+  /// (and literal `*` fields) with backslashes.
+  ///
+  /// The result includes the password verbatim.
+  ///
+  pub fn to_line(&self) -> String {
+    let field = |value: &Option<String>| match value.as_deref() {
+      None => "*".to_string(),
+      Some("*") => "\\*".to_string(),
+      Some(value) => escape(value),
+    };
+
+    format!("{}:{}:{}:{}:{}",
+      field(&self.hostname),
+      field(&self.port),
+      field(&self.database),
+      field(&self.username),
+      escape(&self.password))
+  }
+
+  /// Parse a line of a password file, returning `None` for comments and
+  /// lines having less than five fields (which LibPQ ignores).
+  ///
+  fn parse(line: &str) -> Option<Self> {
+    if line.starts_with('#') { return None }
+
+    let mut fields = Vec::<Option<String>>::new();
+    let mut field = String::new();
+    let mut escaped = false;
+    let mut wildcard = true;
+
+    for c in line.chars() {
+      match c {
+        '\\' if ! escaped => {
+          escaped = true;
+          wildcard = false;
+          continue;
+        },
+        ':' if ! escaped => {
+          // The password ends at the first unescaped colon
+          if fields.len() == 4 { break }
+          fields.push(match wildcard && field == "*" {
+            true => None,
+            false => Some(std::mem::take(&mut field)),
+          });
+          field.clear();
+          wildcard = true;
+        },
+        c => field.push(c),
+      }
+      escaped = false;
+    }
+
+    if fields.len() < 4 { return None }
+
+    let mut fields = fields.into_iter();
+    Some(Self {
+      hostname: fields.next()?,
+      port: fields.next()?,
+      database: fields.next()?,
+      username: fields.next()?,
+      password: field,
+    })
+  }
+}
+
+/* ========================================================================== *
+ * PASSWORD FILE                                                              *
+ * ========================================================================== */
+
+/// A line of a password file: either an entry, or anything else (comments,
+/// blank or malformed lines) preserved verbatim.
+///
+#[derive(Clone)]
+enum PQPassLine {
+  Entry(PQPassEntry),
+  Other(String),
+}
+
+/// The contents of a password file, preserving comments and the order of its
+/// entries (the first matching one wins).
+///
+/// See [The Password File](https://www.postgresql.org/docs/current/libpq-pgpass.html)
+///
+#[derive(Clone, Default)]
+pub struct PQPassFile {
+  lines: Vec<PQPassLine>,
+}
+
+impl Debug for PQPassFile {
+  /// Format this [`PQPassFile`] with all passwords redacted.
+  ///
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("PQPassFile")
+      .field("entries", &self.entries().collect::<Vec<_>>())
+      .finish()
+  }
+}
+
+impl From<&str> for PQPassFile {
+  /// Parse the contents of a password file.
+  ///
+  fn from(contents: &str) -> Self {
+    let lines = contents
+      .lines()
+      .map(|line| line.strip_suffix('\r').unwrap_or(line))
+      .map(|line| match PQPassEntry::parse(line) {
+        Some(entry) => PQPassLine::Entry(entry),
+        None => PQPassLine::Other(line.to_string()),
+      })
+      .collect();
+
+    Self { lines }
+  }
+}
+
+impl PQPassFile {
+  /// Return the path of the password file LibPQ uses by default, from the
+  /// `PGPASSFILE` environment variable or `~/.pgpass`.
+  ///
+  /// The home directory is taken from the `HOME` environment variable, while
+  /// LibPQ looks it up in the password database (`getpwuid`): the two only
+  /// differ when `HOME` was changed (or unset) for the current process.
+  ///
+  pub fn default_path() -> Option<PathBuf> {
+    std::env::var_os("PGPASSFILE")
+      .map(PathBuf::from)
+      .or_else(|| home_file(".pgpass"))
+  }
+
+  /// Read and parse the specified password file.
+  ///
+  /// Unlike LibPQ, this does _not_ check the file's permissions: see
+  /// [`PQPassFile::check_permissions`].
+  ///
+  pub fn read(file: &Path) -> PQResult<Self> {
+    std::fs::read_to_string(file)
+      .map(|contents| Self::from(contents.as_str()))
+      .map_err(|err| format!("Error reading password file {}: {}", file.display(), err).into())
+  }
+
+  /// Check that the specified password file is a plain file, without any
+  /// group or world access, otherwise LibPQ ignores it.
+  ///
+  pub fn check_permissions(file: &Path) -> PQResult<()> {
+    let metadata = std::fs::metadata(file)
+      .map_err(|err| format!("Error accessing password file {}: {}", file.display(), err))?;
+
+    if ! metadata.is_file() {
+      return Err(format!("Password file {} is not a plain file", file.display()).into());
+    }
+
+    match metadata.permissions().mode() & 0o077 {
+      0 => Ok(()),
+      _ => Err(format!("Password file {} has group or world access; permissions should be u=rw (0600) or less", file.display()).into()),
+    }
+  }
+
+  /// Write this password file to the specified path, with `0600` permissions.
+  ///
+  pub fn write(&self, file: &Path) -> PQResult<()> {
+    let error = |err: std::io::Error| format!("Error writing password file {}: {}", file.display(), err);
+
+    let mut output = std::fs::OpenOptions::new()
+      .write(true)
+      .create(true)
+      .truncate(true)
+      .mode(0o600)
+      .open(file)
+      .map_err(error)?;
+
+    // Existing files keep their permissions when opened: fix them
+    output.set_permissions(std::fs::Permissions::from_mode(0o600)).map_err(error)?;
+    output.write_all(self.to_contents().as_bytes()).map_err(error)?;
+    Ok(())
+  }
+
+  /// Render the contents of this password file, including all passwords.
+  ///
+  pub fn to_contents(&self) -> String {
+    self.lines.iter().map(|line| match line {
+      PQPassLine::Entry(entry) => format!("{}\n", entry.to_line()),
+      PQPassLine::Other(line) => format!("{}\n", line),
+    }).collect()
+  }
+
+  /// Iterate over all entries of this password file, in order.
+  ///
+  pub fn entries(&self) -> impl Iterator<Item = &PQPassEntry> {
+    self.lines.iter().filter_map(|line| match line {
+      PQPassLine::Entry(entry) => Some(entry),
+      PQPassLine::Other(_) => None,
+    })
+  }
+
+  /// Return the password of the first entry matching the specified host,
+  /// port, database and user names, applying LibPQ's defaults.
+  ///
+  /// An empty (or default socket directory) host matches `localhost` and an
+  /// empty port matches `5432`, while an empty database or user name never
+  /// matches.
+  ///
+  pub fn lookup(&self, hostname: &str, port: &str, database: &str, username: &str) -> Option<&str> {
+    if database.is_empty() || username.is_empty() { return None }
+
+    let hostname = match hostname {
+      "" => DEFAULT_HOST,
+      hostname if hostname == DEFAULT_SOCKET_DIR => DEFAULT_HOST,
+      hostname => hostname,
+    };
+
+    let port = match port {
+      "" => DEFAULT_PORT,
+      port => port,
+    };
+
+    self.entries()
+      .find(|entry| entry.matches(hostname, port, database, username))
+      .map(|entry| entry.password.as_str())
+  }
+
+  /// Add an entry, replacing the password of the first entry with the same
+  /// (non-password) fields, or appending it after all others.
+  ///
+  pub fn upsert(&mut self, entry: PQPassEntry) {
+    let existing = self.lines.iter_mut().find_map(|line| match line {
+      PQPassLine::Entry(existing) if existing.same_key(&entry) => Some(existing),
+      _ => None,
+    });
+
+    match existing {
+      Some(existing) => existing.password = entry.password,
+      None => self.lines.push(PQPassLine::Entry(entry)),
+    }
+  }
+
+  /// Remove all entries with exactly the same (non-password) fields as the
+  /// specified one, returning whether any was removed.
+  ///
+  pub fn remove(&mut self, entry: &PQPassEntry) -> bool {
+    let length = self.lines.len();
+
+    self.lines.retain(|line| match line {
+      PQPassLine::Entry(existing) => ! existing.same_key(entry),
+      PQPassLine::Other(_) => true,
+    });
+
+    self.lines.len() != length
+  }
+}
+
+/* ========================================================================== *
+ * LOOKUP                                                                     *
+ * ========================================================================== */
+
+/// Return the password LibPQ would use to connect to the (first) host of the
+/// specified [`PQConninfo`].
+///
+/// An explicit `password` wins; otherwise the password file (from `passfile`,
+/// `PGPASSFILE` or `~/.pgpass`) is searched with host, port, database and
+/// user names resolved like LibPQ does, falling back to its defaults and the
+/// environment. Password files LibPQ would ignore (missing, or with group or
+/// world access) yield `None`.
+///
+/// See [The Password File](https://www.postgresql.org/docs/current/libpq-pgpass.html)
+///
+pub fn password_for(info: &PQConninfo) -> PQResult<Option<String>> {
+  let defaults = PQConninfo::from_libpq_defaults()?;
+  let value = |key: &str| info.get(key)
+    .or_else(|| defaults.get(key))
+    .filter(|value| ! value.is_empty())
+    .map(|value| value.to_string());
+
+  if let Some(password) = value("password") {
+    return Ok(Some(password));
+  }
+
+  let file = match value("passfile").map(PathBuf::from).or_else(|| home_file(".pgpass")) {
+    Some(file) => file,
+    None => return Ok(None),
+  };
+
+  if let Err(error) = PQPassFile::check_permissions(&file) {
+    debug!("Ignoring password file: {}", error);
+    return Ok(None);
+  }
+
+  // Only the first host (and its port) is considered
+  let first = |key: &str| value(key)
+    .and_then(|value| value.split(',').next().map(str::to_string))
+    .unwrap_or_default();
+
+  let hostname = match first("host") {
+    host if host.is_empty() => first("hostaddr"),
+    host => host,
+  };
+
+  let username = value("user").unwrap_or_default();
+  let database = value("dbname").unwrap_or_else(|| username.clone());
+
+  Ok(PQPassFile::read(&file)?
+    .lookup(&hostname, &first("port"), &database, &username)
+    .map(str::to_string))
+}
+
+/// Escape `\` and `:` in a field of a password file.
+///
+fn escape(value: &str) -> String {
+  value.replace('\\', "\\\\").replace(':', "\\:")
+}
+
+/* ========================================================================== *
+ * TESTS                                                                      *
+ * ========================================================================== */
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn escaped_fields() {
+    let entry = PQPassEntry::parse(r"db\:host:*:\*:user\\name:pass:word").unwrap();
+    assert_eq!(entry.hostname.as_deref(), Some("db:host"));
+    assert_eq!(entry.port, None);
+    assert_eq!(entry.database.as_deref(), Some("*"));
+    assert_eq!(entry.username.as_deref(), Some(r"user\name"));
+    // The password ends at the first unescaped colon
+    assert_eq!(entry.password, "pass");
+
+    let entry = PQPassEntry::new("db:host", "*", "*", r"user\name", "pass:word");
+    let mut parsed = PQPassEntry::parse(&entry.to_line()).unwrap();
+    assert_eq!(parsed, entry);
+
+    parsed.database = Some("*".to_string());
+    assert_eq!(parsed.to_line(), r"db\:host:*:\*:user\\name:pass\:word");
+    assert_eq!(PQPassEntry::parse(&parsed.to_line()).unwrap(), parsed);
+  }
+
+  #[test]
+  fn short_lines() {
+    assert!(PQPassEntry::parse("host:5432:db:user").is_none());
+    assert!(PQPassEntry::parse(r"host:5432:db:user\:password").is_none());
+    assert!(PQPassEntry::parse("# host:5432:db:user:password").is_none());
+    assert_eq!(PQPassEntry::parse("host:5432:db:user:").unwrap().password, "");
+
+    // Short lines are ignored, but preserved
+    let file = PQPassFile::from("host:5432:db:user\nhost:5432:db:user:password\n");
+    assert_eq!(file.entries().count(), 1);
+    assert_eq!(file.to_contents(), "host:5432:db:user\nhost:5432:db:user:password\n");
+  }
+
+  #[test]
+  fn lookup_defaults() {
+    let file = PQPassFile::from("localhost:5432:db:user:local\n*:5433:*:user:other\n");
+
+    assert_eq!(file.lookup("", "", "db", "user"), Some("local"));
+    assert_eq!(file.lookup("/tmp", "5432", "db", "user"), Some("local"));
+    assert_eq!(file.lookup("localhost", "", "db", "user"), Some("local"));
+    assert_eq!(file.lookup("/var/run/postgresql", "", "db", "user"), None);
+    assert_eq!(file.lookup("example.com", "5433", "any", "user"), Some("other"));
+    assert_eq!(file.lookup("example.com", "5433", "", "user"), None);
+    assert_eq!(file.lookup("example.com", "5433", "any", ""), None);
+  }
+
+  #[test]
+  fn edit_preserving_comments() {
+    let mut file = PQPassFile::from("# first\nhost:*:db:user:old\n\n# last\n");
+
+    file.upsert(PQPassEntry::new("host", "*", "db", "user", "new"));
+    file.upsert(PQPassEntry::new("other", "5432", "*", "user", "added"));
+    assert_eq!(file.to_contents(), "# first\nhost:*:db:user:new\n\n# last\nother:5432:*:user:added\n");
+
+    assert!(file.remove(&PQPassEntry::new("host", "*", "db", "user", "")));
+    assert!(! file.remove(&PQPassEntry::new("host", "*", "db", "user", "")));
+    assert_eq!(file.to_contents(), "# first\n\n# last\nother:5432:*:user:added\n");
+  }
+}
@@ -0,0 +1,467 @@
+//! A pool of [`PQConnection`]s shared by multiple callers.
+
+use crate::connection::PQConnection;
+use crate::connection::PQConnectionStatus;
+use crate::connection::PQTransactionStatus;
+use crate::conninfo::PQConninfo;
+use crate::credentials::PQCredentialProvider;
+use crate::debug::*;
+use crate::errors::*;
+use crate::security::PQSecurityPolicy;
+use crate::session::PQSessionReset;
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use std::time::Instant;
+
+/* ========================================================================== *
+ * OPTIONS                                                                    *
+ * ========================================================================== */
+
+/// Options for a [`PQPool`].
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PQPoolOptions {
+  /// The minimum number of connections kept open by the pool.
+  pub min_size: usize,
+  /// The maximum number of connections (idle or in use) opened by the pool.
+  pub max_size: usize,
+  /// How long to wait for a connection before failing.
+  pub acquire_timeout: Duration,
+  /// How long a connection can stay idle in the pool before being closed.
+  pub idle_timeout: Option<Duration>,
+  /// How long a connection can be used for, before being closed.
+  pub max_lifetime: Option<Duration>,
+  /// How to reset _dirty_ sessions when connections are returned to the pool.
+  pub session_reset: Option<PQSessionReset>,
+  /// The security policy enforced on all connections.
+  pub security_policy: Option<PQSecurityPolicy>,
+}
+
+impl Default for PQPoolOptions {
+  /// Up to 10 connections, waiting 30 seconds to acquire them, and closing
+  /// them after 10 minutes idle or 30 minutes since they were opened. Dirty
+  /// sessions are reset with `DISCARD ALL` when returned to the pool.
+  ///
+  fn default() -> Self {
+    Self {
+      min_size: 0,
+      max_size: 10,
+      acquire_timeout: Duration::from_secs(30),
+      idle_timeout: Some(Duration::from_secs(600)),
+      max_lifetime: Some(Duration::from_secs(1800)),
+      session_reset: Some(PQSessionReset::DiscardAll),
+      security_policy: None,
+    }
+  }
+}
+
+impl PQPoolOptions {
+  /// Set the minimum number of connections kept open by the pool.
+  ///
+  pub fn min_size(mut self, min_size: usize) -> Self {
+    self.min_size = min_size;
+    self
+  }
+
+  /// Set the maximum number of connections opened by the pool.
+  ///
+  pub fn max_size(mut self, max_size: usize) -> Self {
+    self.max_size = max_size;
+    self
+  }
+
+  /// Set how long to wait for a connection before failing.
+  ///
+  pub fn acquire_timeout(mut self, acquire_timeout: Duration) -> Self {
+    self.acquire_timeout = acquire_timeout;
+    self
+  }
+
+  /// Set how long a connection can stay idle before being closed.
+  ///
+  pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+    self.idle_timeout = idle_timeout;
+    self
+  }
+
+  /// Set how long a connection can be used for, before being closed.
+  ///
+  pub fn max_lifetime(mut self, max_lifetime: Option<Duration>) -> Self {
+    self.max_lifetime = max_lifetime;
+    self
+  }
+
+  /// Set how to reset dirty sessions when connections are returned to the
+  /// pool (or `None` to reuse connections as they are).
+  ///
+  pub fn session_reset(mut self, session_reset: Option<PQSessionReset>) -> Self {
+    self.session_reset = session_reset;
+    self
+  }
+
+  /// Set the security policy enforced on all connections (or `None` to
+  /// enforce none).
+  ///
+  pub fn security_policy(mut self, security_policy: Option<PQSecurityPolicy>) -> Self {
+    self.security_policy = security_policy;
+    self
+  }
+}
+
+/* ========================================================================== *
+ * POOL                                                                       *
+ * ========================================================================== */
+
+/// A connection held by the pool, with its timestamps.
+///
+struct PQPoolEntry {
+  connection: PQConnection,
+  created: Instant,
+  returned: Instant,
+}
+
+impl PQPoolEntry {
+  /// Returns `true` if this entry outlived the pool's idle or max lifetime.
+  ///
+  fn is_expired(&self, options: &PQPoolOptions, now: Instant) -> bool {
+    let idle = options.idle_timeout
+      .map(|timeout| now.duration_since(self.returned) >= timeout)
+      .unwrap_or(false);
+    let old = options.max_lifetime
+      .map(|lifetime| now.duration_since(self.created) >= lifetime)
+      .unwrap_or(false);
+    idle || old
+  }
+
+  /// Returns `true` if the connection is alive and not in a transaction.
+  ///
+  fn is_healthy(&self) -> bool {
+    // Consuming input updates the status if the server closed the connection
+    self.connection.pq_consume_input().is_ok()
+      && self.connection.pq_status() == PQConnectionStatus::Ok
+      && self.connection.pq_transaction_status() == PQTransactionStatus::Idle
+  }
+}
+
+/// The mutable state of the pool.
+///
+struct PQPoolState {
+  /// Idle connections, the most recently returned at the back.
+  idle: VecDeque<PQPoolEntry>,
+  /// The total number of connections (idle, in use or being opened).
+  size: usize,
+}
+
+/// Shared part of the pool, referenced by the pool and all its connections.
+///
+struct PQPoolInner {
+  id: usize,
+  conninfo: PQConninfo,
+  credentials: Option<Arc<dyn PQCredentialProvider>>,
+  options: PQPoolOptions,
+  state: Mutex<PQPoolState>,
+  available: Condvar,
+}
+
+debug_self!(PQPoolInner, id);
+
+impl PQPoolInner {
+  /// Lock our state, ignoring poisoning (the state is always consistent).
+  ///
+  fn state(&self) -> MutexGuard<'_, PQPoolState> {
+    self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+  }
+
+  /// Open a new connection, accounting for it in our size.
+  ///
+  fn connect(&self) -> PQResult<PQPoolEntry> {
+    let conninfo = self.conninfo.clone();
+    let connection = match (&self.credentials, self.options.security_policy.clone()) {
+      (Some(provider), Some(policy)) => PQConnection::connect_with_credentials_and_policy(conninfo, provider.clone(), policy),
+      (Some(provider), None) => PQConnection::connect_with_credentials(conninfo, provider.clone()),
+      (None, Some(policy)) => PQConnection::connect_with_policy(conninfo, policy),
+      (None, None) => PQConnection::try_from(conninfo),
+    };
+
+    match connection {
+      Ok(connection) => {
+        let now = Instant::now();
+        Ok(PQPoolEntry { connection, created: now, returned: now })
+      },
+      Err(error) => {
+        self.state().size -= 1;
+        self.available.notify_one();
+        Err(error)
+      },
+    }
+  }
+
+  /// Return a connection to the pool (or close it if it can't be reused).
+  ///
+  fn release(&self, mut entry: PQPoolEntry) {
+    let now = Instant::now();
+
+    let reusable = match entry.connection.pq_transaction_status() {
+      PQTransactionStatus::Idle => entry.connection.pq_status() == PQConnectionStatus::Ok,
+      status => {
+        debug!("Rejecting connection returned to pool in transaction ({:?})", status);
+        false
+      },
+    };
+
+    let reusable = reusable && match &self.options.session_reset {
+      Some(reset) => entry.connection.reset_session_if_dirty(reset)
+        .map_err(|error| debug!("Unable to reset session: {}", error))
+        .is_ok(),
+      None => true,
+    };
+
+    let discarded = match reusable && (! entry.is_expired(&self.options, now)) {
+      true => {
+        entry.returned = now;
+        self.state().idle.push_back(entry);
+        None
+      },
+      false => {
+        self.state().size -= 1;
+        Some(entry)
+      },
+    };
+
+    self.available.notify_one();
+    drop(discarded);
+  }
+}
+
+/// A pool of [`PQConnection`]s, all opened with the same [`PQConninfo`].
+///
+/// Connections are checked out with [`PQPool::acquire`] and returned to the
+/// pool when the [`PQPooledConnection`] is dropped. Connections returned in
+/// the middle of a transaction (or broken) are closed, not reused.
+///
+#[derive(Clone)]
+pub struct PQPool {
+  inner: Arc<PQPoolInner>,
+}
+
+impl std::fmt::Debug for PQPool {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("PQPool")
+      .field("id", &self.inner.id)
+      .finish()
+  }
+}
+
+impl PQPool {
+  /// Create a new [`PQPool`], immediately opening the minimum number of
+  /// connections specified in its options.
+  ///
+  pub fn new(conninfo: PQConninfo, options: PQPoolOptions) -> PQResult<Self> {
+    Self::create(conninfo, None, options)
+  }
+
+  /// Create a new [`PQPool`] whose connections obtain their credentials from
+  /// the specified provider, right before each one is opened.
+  ///
+  /// See [`PQConnection::connect_with_credentials`]
+  ///
+  pub fn with_credentials(
+    conninfo: PQConninfo,
+    provider: Arc<dyn PQCredentialProvider>,
+    options: PQPoolOptions,
+  ) -> PQResult<Self> {
+    Self::create(conninfo, Some(provider), options)
+  }
+
+  /// Create a new [`PQPool`], opening its minimum number of connections.
+  ///
+  fn create(
+    conninfo: PQConninfo,
+    credentials: Option<Arc<dyn PQCredentialProvider>>,
+    options: PQPoolOptions,
+  ) -> PQResult<Self> {
+    if options.max_size == 0 {
+      return Err("Pool maximum size must be greater than zero".into())
+    } else if options.min_size > options.max_size {
+      return Err("Pool minimum size must not exceed its maximum size".into())
+    }
+
+    let state = PQPoolState { idle: VecDeque::new(), size: 0 };
+
+    let pool = Self {
+      inner: Arc::new(debug_create!(PQPoolInner {
+        id: debug_id(),
+        conninfo,
+        credentials,
+        options,
+        state: Mutex::new(state),
+        available: Condvar::new(),
+      })),
+    };
+
+    pool.prune()?;
+    Ok(pool)
+  }
+
+  /// Return the options of this pool.
+  ///
+  pub fn options(&self) -> &PQPoolOptions {
+    &self.inner.options
+  }
+
+  /// Return the total number of connections (idle or in use) in the pool.
+  ///
+  pub fn size(&self) -> usize {
+    self.inner.state().size
+  }
+
+  /// Return the number of idle connections in the pool.
+  ///
+  pub fn idle(&self) -> usize {
+    self.inner.state().idle.len()
+  }
+
+  /// Check out a connection from the pool, opening a new one if none is idle
+  /// and the pool is not full, or waiting up to the pool's acquisition timeout
+  /// for one to be returned otherwise.
+  ///
+  /// Idle connections are health-checked before being handed out.
+  ///
+  pub fn acquire(&self) -> PQResult<PQPooledConnection> {
+    let inner = &self.inner;
+    let deadline = Instant::now().checked_add(inner.options.acquire_timeout);
+    let mut discarded = Vec::<PQPoolEntry>::new();
+    let mut state = inner.state();
+
+    let entry = loop {
+      let now = Instant::now();
+
+      // Reuse the most recently returned connection, if any
+      if let Some(entry) = state.idle.pop_back() {
+        if (! entry.is_expired(&inner.options, now)) && entry.is_healthy() {
+          break entry;
+        }
+
+        debug!("Closing expired or unhealthy connection {:?}", entry.connection);
+        state.size -= 1;
+        discarded.push(entry);
+        continue;
+      }
+
+      // Open a new connection if there's room for it
+      if state.size < inner.options.max_size {
+        state.size += 1;
+        drop(state);
+        drop(discarded);
+        break inner.connect()?;
+      }
+
+      // Wait for a connection to be returned to the pool (forever, if the
+      // timeout is too large to be represented)
+      state = match deadline {
+        Some(deadline) if now >= deadline => {
+          return Err(format!("Timed out acquiring connection after {:?}", inner.options.acquire_timeout).into())
+        },
+        Some(deadline) => inner.available
+          .wait_timeout(state, deadline - now)
+          .unwrap_or_else(|poisoned| poisoned.into_inner())
+          .0,
+        None => inner.available
+          .wait(state)
+          .unwrap_or_else(|poisoned| poisoned.into_inner()),
+      };
+    };
+
+    Ok(PQPooledConnection { pool: self.inner.clone(), entry: Some(entry) })
+  }
+
+  /// Close idle connections exceeding their idle timeout or maximum lifetime,
+  /// then open new connections up to the pool's minimum size.
+  ///
+  pub fn prune(&self) -> PQResult<()> {
+    let inner = &self.inner;
+    let now = Instant::now();
+
+    let discarded = {
+      let mut state = inner.state();
+      let (expired, retained) = state.idle
+        .drain(..)
+        .partition::<Vec<_>, _>(|entry| entry.is_expired(&inner.options, now));
+      state.idle = retained.into();
+      state.size -= expired.len();
+      expired
+    };
+    drop(discarded);
+
+    loop {
+      {
+        let mut state = inner.state();
+        if state.size >= inner.options.min_size { break }
+        state.size += 1;
+      }
+
+      let entry = inner.connect()?;
+      inner.release(entry);
+    }
+
+    Ok(())
+  }
+}
+
+/* ========================================================================== *
+ * POOLED CONNECTION                                                          *
+ * ========================================================================== */
+
+/// A [`PQConnection`] checked out from a [`PQPool`].
+///
+/// The connection is returned to the pool when this is dropped.
+///
+pub struct PQPooledConnection {
+  pool: Arc<PQPoolInner>,
+  entry: Option<PQPoolEntry>,
+}
+
+impl std::fmt::Debug for PQPooledConnection {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("PQPooledConnection")
+      .field("pool", &self.pool.id)
+      .field("connection", &self.deref())
+      .finish()
+  }
+}
+
+impl Deref for PQPooledConnection {
+  type Target = PQConnection;
+
+  fn deref(&self) -> &PQConnection {
+    &self.entry.as_ref().unwrap().connection
+  }
+}
+
+impl Drop for PQPooledConnection {
+  /// Return the connection to its pool.
+  ///
+  fn drop(&mut self) {
+    if let Some(entry) = self.entry.take() {
+      self.pool.release(entry);
+    }
+  }
+}
+
+impl PQPooledConnection {
+  /// Detach this connection from its pool, taking ownership of it.
+  ///
+  /// The pool will then be free to open a new connection in its place.
+  ///
+  pub fn detach(mut self) -> PQConnection {
+    let entry = self.entry.take().unwrap();
+    self.pool.state().size -= 1;
+    self.pool.available.notify_one();
+    entry.connection
+  }
+}
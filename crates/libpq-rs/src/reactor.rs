@@ -0,0 +1,322 @@
+//! A shared reactor polling many connections from a single thread.
+
+use crate::connection::PQConnection;
+use crate::connection::PQNextResult;
+use crate::connection::PQPollingInterest;
+use crate::debug::*;
+use crate::errors::*;
+use crate::response::PQResponse;
+use polling::Event;
+use polling::Events;
+use polling::Poller;
+use std::collections::HashMap;
+use std::os::fd::BorrowedFd;
+use std::time::Duration;
+
+/* ========================================================================== *
+ * TASKS                                                                      *
+ * ========================================================================== */
+
+/// A per-connection state machine driven by a [`PQReactor`].
+///
+/// The task is invoked whenever its connection becomes ready for the interest
+/// it last declared, and returns its next interest or `None` when done.
+///
+pub trait PQReactorTask {
+  fn ready(&mut self, connection: &PQConnection, interest: PQPollingInterest) -> PQResult<Option<PQPollingInterest>>;
+}
+
+impl <F> PQReactorTask for F
+where
+  F: FnMut(&PQConnection, PQPollingInterest) -> PQResult<Option<PQPollingInterest>>,
+{
+  fn ready(&mut self, connection: &PQConnection, interest: PQPollingInterest) -> PQResult<Option<PQPollingInterest>> {
+    self(connection, interest)
+  }
+}
+
+/// A [`PQReactorTask`] sending a command, collecting all its results, and
+/// handing them over to a callback.
+///
+pub struct PQQueryTask<F: FnOnce(PQResult<Vec<PQResponse>>)> {
+  command: Option<String>,
+  responses: Vec<PQResponse>,
+  callback: Option<F>,
+}
+
+impl <F: FnOnce(PQResult<Vec<PQResponse>>)> PQQueryTask<F> {
+  /// Create a new [`PQQueryTask`] for the specified command and callback.
+  ///
+  /// The task must be submitted with a [`PQPollingInterest::Writable`]
+  /// interest, so that the command is sent when the connection is writable.
+  ///
+  pub fn new(command: String, callback: F) -> Self {
+    Self { command: Some(command), responses: Vec::new(), callback: Some(callback) }
+  }
+
+  /// Invoke our callback (only once).
+  ///
+  fn complete(&mut self, result: PQResult<Vec<PQResponse>>) {
+    if let Some(callback) = self.callback.take() {
+      callback(result)
+    }
+  }
+
+  /// Send the command, flush it, and collect all its results.
+  ///
+  fn advance(&mut self, connection: &PQConnection) -> PQResult<Option<PQPollingInterest>> {
+    if let Some(command) = self.command.take() {
+      connection.pq_send_query(command)?;
+    }
+
+    loop {
+      match connection.try_get_result()? {
+        PQNextResult::Ready(response) => self.responses.push(response),
+        PQNextResult::Wait(interest) => return Ok(Some(interest)),
+        PQNextResult::Done => {
+          let responses = std::mem::take(&mut self.responses);
+          self.complete(Ok(responses));
+          return Ok(None);
+        },
+      }
+    }
+  }
+}
+
+impl <F: FnOnce(PQResult<Vec<PQResponse>>)> PQReactorTask for PQQueryTask<F> {
+  fn ready(&mut self, connection: &PQConnection, _interest: PQPollingInterest) -> PQResult<Option<PQPollingInterest>> {
+    self.advance(connection).inspect_err(|error| self.complete(Err(error.clone())))
+  }
+}
+
+/* ========================================================================== *
+ * REACTOR                                                                    *
+ * ========================================================================== */
+
+/// A connection registered with a [`PQReactor`], and its current task.
+///
+struct PQReactorEntry {
+  connection: PQConnection,
+  socket: i32,
+  task: Option<(PQPollingInterest, Box<dyn PQReactorTask>)>,
+}
+
+impl PQReactorEntry {
+  /// Return the polling [`Event`] for this entry's current interest.
+  ///
+  fn event(&self, key: usize) -> Event {
+    match self.task {
+      Some((PQPollingInterest::Readable, _)) => Event::readable(key),
+      Some((PQPollingInterest::Writable, _)) => Event::writable(key),
+      None => Event::none(key),
+    }
+  }
+
+  /// Return the socket this entry was registered with, or `None` if LibPQ
+  /// has since closed it (e.g. when the server dropped the connection).
+  ///
+  fn source(&self) -> Option<BorrowedFd<'_>> {
+    match self.connection.pq_socket() == self.socket {
+      true => Some(unsafe { BorrowedFd::borrow_raw(self.socket) }),
+      false => None,
+    }
+  }
+}
+
+/// A long-lived reactor, polling the sockets of many [`PQConnection`]s and
+/// dispatching readiness to their [`PQReactorTask`]s.
+///
+/// Connections are registered once (and owned by the reactor until they're
+/// deregistered), switched to nonblocking mode, and can be handed new tasks
+/// whenever their previous task is done.
+///
+/// As resetting a connection might change its socket, connections must be
+/// deregistered before being reset, and registered again afterwards.
+///
+pub struct PQReactor {
+  id: usize,
+  poller: Poller,
+  events: Events,
+  entries: HashMap<usize, PQReactorEntry>,
+  next_key: usize,
+}
+
+debug_self!(PQReactor, id);
+
+impl Drop for PQReactor {
+  fn drop(&mut self) {
+    debug_drop!(self);
+    for source in self.entries.values().filter_map(PQReactorEntry::source) {
+      let _ = self.poller.delete(source);
+    }
+  }
+}
+
+impl PQReactor {
+  /// Create a new, empty, [`PQReactor`].
+  ///
+  pub fn new() -> PQResult<Self> {
+    let poller = Poller::new()
+      .map_err(| err | format!("Error creating poller: {}", err))?;
+
+    Ok(debug_create!(Self {
+      id: debug_id(),
+      poller,
+      events: Events::new(),
+      entries: HashMap::new(),
+      next_key: 0,
+    }))
+  }
+
+  /// Return the number of connections registered with this reactor.
+  ///
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  /// Returns `true` if no connection is registered with this reactor.
+  ///
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  /// Return the [`PQConnection`] registered under the specified key.
+  ///
+  pub fn connection(&self, key: usize) -> Option<&PQConnection> {
+    self.entries.get(&key).map(|entry| &entry.connection)
+  }
+
+  /// Returns `true` if the connection registered under the specified key
+  /// has a task in progress.
+  ///
+  pub fn is_busy(&self, key: usize) -> bool {
+    self.entries.get(&key).map(|entry| entry.task.is_some()).unwrap_or(false)
+  }
+
+  /// Register a [`PQConnection`] with this reactor, returning its key.
+  ///
+  /// The connection is switched to nonblocking mode.
+  ///
+  pub fn register(&mut self, connection: PQConnection) -> PQResult<usize> {
+    let socket = connection.pq_socket();
+    if socket < 0 {
+      return Err("Connection has no socket".into());
+    }
+
+    connection.pq_setnonblocking(true)?;
+
+    let key = self.next_key;
+    let entry = PQReactorEntry { connection, socket, task: None };
+
+    unsafe {
+      self.poller.add(&BorrowedFd::borrow_raw(socket), entry.event(key))
+        .map_err(| err | format!("Error adding to poller: {}", err))?;
+    }
+
+    self.next_key += 1;
+    self.entries.insert(key, entry);
+    Ok(key)
+  }
+
+  /// Deregister a [`PQConnection`] from this reactor, returning it.
+  ///
+  /// Any task in progress is dropped.
+  ///
+  pub fn deregister(&mut self, key: usize) -> PQResult<PQConnection> {
+    let entry = self.entries.remove(&key)
+      .ok_or_else(|| format!("No connection registered with key {}", key))?;
+
+    // A closed socket was already removed from the poller by the kernel
+    if let Some(source) = entry.source() {
+      self.poller.delete(source)
+        .map_err(| err | format!("Error deleting from poller: {}", err))?;
+    }
+
+    Ok(entry.connection)
+  }
+
+  /// Submit a new task for the connection registered under the specified
+  /// key, to be invoked when the connection is ready for the given interest.
+  ///
+  pub fn submit(&mut self, key: usize, interest: PQPollingInterest, task: Box<dyn PQReactorTask>) -> PQResult<()> {
+    let entry = self.entries.get_mut(&key)
+      .ok_or_else(|| format!("No connection registered with key {}", key))?;
+
+    if entry.task.is_some() {
+      return Err(format!("Connection with key {} already has a task in progress", key).into());
+    }
+
+    let source = entry.source()
+      .ok_or_else(|| format!("Connection with key {} lost its socket", key))?;
+
+    self.poller.modify(source, match interest {
+      PQPollingInterest::Readable => Event::readable(key),
+      PQPollingInterest::Writable => Event::writable(key),
+    }).map_err(| err | format!("Error modifying poller: {}", err))?;
+
+    entry.task = Some((interest, task));
+    Ok(())
+  }
+
+  /// Wait (up to the specified timeout, or forever) for any registered
+  /// connection to become ready, and dispatch readiness to its task.
+  ///
+  /// This returns the keys of all connections whose tasks completed (or
+  /// failed) in this turn. Tasks of failed connections are dropped, but the
+  /// connections themselves stay registered.
+  ///
+  pub fn turn(&mut self, timeout: Option<Duration>) -> PQResult<Vec<(usize, PQResult<()>)>> {
+    self.events.clear();
+    self.poller.wait(&mut self.events, timeout)
+      .map_err(| err | format!("Error waiting on poller: {}", err))?;
+
+    let mut completed = Vec::<(usize, PQResult<()>)>::new();
+
+    for event in self.events.iter() {
+      let entry = match self.entries.get_mut(&event.key) {
+        Some(entry) => entry,
+        None => continue,
+      };
+
+      let (interest, mut task) = match entry.task.take() {
+        Some(task) => task,
+        None => continue,
+      };
+
+      let ready = match interest {
+        PQPollingInterest::Readable => event.readable,
+        PQPollingInterest::Writable => event.writable,
+      };
+
+      let next = match (ready, event.is_err()) {
+        (_, Some(true)) => Err(PQError::from("Connection error")),
+        (true, _) => task.ready(&entry.connection, interest),
+        (false, _) => Ok(Some(interest)),
+      };
+
+      match next {
+        Ok(Some(interest)) => entry.task = Some((interest, task)),
+        Ok(None) => completed.push((event.key, Ok(()))),
+        Err(error) => completed.push((event.key, Err(error))),
+      }
+
+      // Re-arm our (oneshot) interest for this connection, unless LibPQ
+      // closed its socket (failing any task still waiting on it)
+      let armed = match entry.source() {
+        Some(source) => self.poller.modify(source, entry.event(event.key))
+          .map_err(| err | PQError::from(format!("Error modifying poller: {}", err))),
+        None => Err("Connection lost its socket".into()),
+      };
+
+      // Don't lose the outcome of other tasks, just fail this connection's
+      if let Err(error) = armed {
+        match entry.task.take() {
+          Some(_) => completed.push((event.key, Err(error))),
+          None => debug!("Unable to re-arm connection with key {}: {}", event.key, error),
+        }
+      }
+    }
+
+    Ok(completed)
+  }
+}
@@ -3,6 +3,8 @@
 use crate::debug::*;
 use crate::errors::*;
 use crate::ffi::*;
+use std::ptr::null_mut;
+use std::sync::Arc;
 
 /// The result status of the command.
 ///
@@ -56,6 +58,51 @@ impl From<pq_sys::ExecStatusType> for PQResponseStatus {
   }
 }
 
+/// A field of an error report associated with a [`PQResponse`].
+///
+/// See [`PQresultErrorField`](https://www.postgresql.org/docs/current/libpq-exec.html#LIBPQ-PQRESULTERRORFIELD)
+///
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub enum PQResponseErrorField {
+  /// The severity (localized).
+  Severity = pq_sys::PG_DIAG_SEVERITY,
+  /// The severity (never localized).
+  SeverityNonlocalized = pq_sys::PG_DIAG_SEVERITY_NONLOCALIZED,
+  /// The SQLSTATE code for the error.
+  Sqlstate = pq_sys::PG_DIAG_SQLSTATE,
+  /// The primary human-readable error message.
+  MessagePrimary = pq_sys::PG_DIAG_MESSAGE_PRIMARY,
+  /// An optional secondary error message carrying more detail.
+  MessageDetail = pq_sys::PG_DIAG_MESSAGE_DETAIL,
+  /// An optional suggestion what to do about the problem.
+  MessageHint = pq_sys::PG_DIAG_MESSAGE_HINT,
+  /// The error cursor position as an index into the original statement.
+  StatementPosition = pq_sys::PG_DIAG_STATEMENT_POSITION,
+  /// The error cursor position as an index into an internally-generated command.
+  InternalPosition = pq_sys::PG_DIAG_INTERNAL_POSITION,
+  /// The text of a failed internally-generated command.
+  InternalQuery = pq_sys::PG_DIAG_INTERNAL_QUERY,
+  /// An indication of the context in which the error occurred.
+  Context = pq_sys::PG_DIAG_CONTEXT,
+  /// The name of the schema containing the object associated with the error.
+  SchemaName = pq_sys::PG_DIAG_SCHEMA_NAME,
+  /// The name of the table associated with the error.
+  TableName = pq_sys::PG_DIAG_TABLE_NAME,
+  /// The name of the table column associated with the error.
+  ColumnName = pq_sys::PG_DIAG_COLUMN_NAME,
+  /// The name of the data type associated with the error.
+  DatatypeName = pq_sys::PG_DIAG_DATATYPE_NAME,
+  /// The name of the constraint associated with the error.
+  ConstraintName = pq_sys::PG_DIAG_CONSTRAINT_NAME,
+  /// The file name of the source-code location where the error was reported.
+  SourceFile = pq_sys::PG_DIAG_SOURCE_FILE,
+  /// The line number of the source-code location where the error was reported.
+  SourceLine = pq_sys::PG_DIAG_SOURCE_LINE,
+  /// The name of the source-code function reporting the error.
+  SourceFunction = pq_sys::PG_DIAG_SOURCE_FUNCTION,
+}
+
 /* ========================================================================== */
 
 /// Struct wrapping the LibPQ functions related to a _result_.
@@ -107,6 +154,18 @@ impl PQResponse {
     }
   }
 
+  /// Consumes this [`PQResponse`] returning an error if its
+  /// [status][PQResponse::pq_result_status] indicates the command failed.
+  ///
+  pub fn into_result(self) -> PQResult<Self> {
+    match self.pq_result_status() {
+      PQResponseStatus::BadResponse |
+      PQResponseStatus::FatalError |
+      PQResponseStatus::PipelineAborted => Err(PQError::from(&self)),
+      _ => Ok(self),
+    }
+  }
+
   /// Returns the error message associated with the command, if any.
   ///
   /// See [`PQresultErrorMessage`](https://www.postgresql.org/docs/current/libpq-exec.html#LIBPQ-PQRESULTERRORMESSAGE)
@@ -127,6 +186,16 @@ impl PQResponse {
     }
   }
 
+  /// Returns an individual field of an error report, if any.
+  ///
+  /// See [`PQresultErrorField`](https://www.postgresql.org/docs/current/libpq-exec.html#LIBPQ-PQRESULTERRORFIELD)
+  ///
+  pub fn pq_result_error_field(&self, field: PQResponseErrorField) -> Option<String> {
+    unsafe {
+      to_string_lossy(pq_sys::PQresultErrorField(self.result, field as i32))
+    }
+  }
+
   /// Returns the command status tag from the SQL command that generated the PGresult.
   ///
   /// See [`PQcmdStatus`](https://www.postgresql.org/docs/current/libpq-exec.html#LIBPQ-PQCMDSTATUS)
@@ -151,6 +220,16 @@ impl PQResponse {
     }
   }
 
+  /// Returns the number of bytes allocated for this result.
+  ///
+  /// See [`PQresultMemorySize`](https://www.postgresql.org/docs/current/libpq-misc.html#LIBPQ-PQRESULTMEMORYSIZE)
+  ///
+  pub fn pq_result_memory_size(&self) -> usize {
+    unsafe {
+      pq_sys::PQresultMemorySize(self.result)
+    }
+  }
+
   /// Returns the number of rows (tuples) in the query result.
   ///
   /// See [`PQntuples`](https://www.postgresql.org/docs/current/libpq-exec.html#LIBPQ-PQNTUPLES)
@@ -214,4 +293,113 @@ impl PQResponse {
       }
     }
   }
+
+  /// Returns an empty copy of this result (with a [`PQResponseStatus::TuplesOk`]
+  /// status) holding the same columns, but no rows.
+  ///
+  /// See [`PQcopyResult`](https://www.postgresql.org/docs/current/libpq-misc.html#LIBPQ-PQCOPYRESULT)
+  ///
+  pub(crate) fn copy_columns(&self) -> PQResult<PQResponse> {
+    unsafe {
+      PQResponse::try_from(pq_sys::PQcopyResult(self.result, pq_sys::PG_COPYRES_ATTRS as i32))
+    }
+  }
+
+  /// Appends all rows of another result (with the same columns) to this one,
+  /// copying their values.
+  ///
+  /// See [`PQsetvalue`](https://www.postgresql.org/docs/current/libpq-misc.html#LIBPQ-PQSETVALUE)
+  ///
+  pub(crate) fn append_rows(&mut self, other: &PQResponse) -> PQResult<()> {
+    let columns = self.pq_nfields();
+    if other.pq_nfields() != columns {
+      return Err("Unable to append rows with different columns".into());
+    }
+
+    for row in 0 .. other.pq_ntuples() {
+      let target = self.pq_ntuples();
+
+      for column in 0 .. columns {
+        let appended = unsafe {
+          match other.pq_getisnull(row, column) {
+            true => pq_sys::PQsetvalue(self.result, target, column, null_mut(), -1),
+            false => pq_sys::PQsetvalue(
+              self.result,
+              target,
+              column,
+              pq_sys::PQgetvalue(other.result, row, column),
+              pq_sys::PQgetlength(other.result, row, column),
+            ),
+          }
+        };
+
+        if appended != 1 {
+          return Err("Unable to append row to result".into());
+        }
+      }
+    }
+
+    Ok(())
+  }
+}
+
+/* ========================================================================== *
+ * ROWS                                                                       *
+ * ========================================================================== */
+
+/// A single row of a (shared) [`PQResponse`].
+///
+#[derive(Debug, Clone)]
+pub struct PQRow {
+  response: Arc<PQResponse>,
+  row: i32,
+}
+
+impl From<PQResponse> for Vec<PQRow> {
+  /// Split a [`PQResponse`] into all its rows.
+  ///
+  fn from(response: PQResponse) -> Self {
+    let response = Arc::new(response);
+    (0..response.pq_ntuples())
+      .map(|row| PQRow { response: response.clone(), row })
+      .collect()
+  }
+}
+
+impl PQRow {
+  /// Return the [`PQResponse`] this row belongs to.
+  ///
+  pub fn response(&self) -> &PQResponse {
+    &self.response
+  }
+
+  /// Return the number of this row within its [`PQResponse`].
+  ///
+  pub fn index(&self) -> i32 {
+    self.row
+  }
+
+  /// Return the number of columns (fields) in this row.
+  ///
+  pub fn len(&self) -> i32 {
+    self.response.pq_nfields()
+  }
+
+  /// Returns `true` if this row has no columns.
+  ///
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Tests a column of this row for a null value.
+  ///
+  pub fn is_null(&self, column: i32) -> bool {
+    self.response.pq_getisnull(self.row, column)
+  }
+
+  /// Returns the value of a column of this row.
+  ///
+  pub fn get(&self, column: i32) -> PQResult<Option<String>> {
+    self.response.pq_getvalue(self.row, column)
+  }
 }
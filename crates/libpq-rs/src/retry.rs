@@ -0,0 +1,193 @@
+//! Automatic retry of transactions failing because of transient errors.
+
+use crate::connection::PQConnection;
+use crate::connection::PQConnectionStatus;
+use crate::debug::*;
+use crate::errors::*;
+use crate::transaction::PQTransaction;
+use crate::transaction::PQTransactionOptions;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// SQLSTATE for `serialization_failure`.
+pub static SERIALIZATION_FAILURE: &str = "40001";
+/// SQLSTATE for `deadlock_detected`.
+pub static DEADLOCK_DETECTED: &str = "40P01";
+
+/* ========================================================================== *
+ * OPTIONS AND OUTCOME                                                        *
+ * ========================================================================== */
+
+/// Options controlling how many times (and how often) a transaction is retried.
+///
+/// The delay between attempts starts at `initial_backoff` and is multiplied
+/// by `multiplier` after each failure, never exceeding `max_backoff`.
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PQRetryOptions {
+  pub max_attempts: usize,
+  pub initial_backoff: Duration,
+  pub max_backoff: Duration,
+  pub multiplier: f64,
+}
+
+impl Default for PQRetryOptions {
+  /// Up to 5 attempts, starting with 10 milliseconds backoff, doubling each
+  /// time up to 1 second.
+  ///
+  fn default() -> Self {
+    Self {
+      max_attempts: 5,
+      initial_backoff: Duration::from_millis(10),
+      max_backoff: Duration::from_secs(1),
+      multiplier: 2.0,
+    }
+  }
+}
+
+impl PQRetryOptions {
+  /// Set the maximum number of attempts (including the first one).
+  ///
+  pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+    self.max_attempts = max_attempts;
+    self
+  }
+
+  /// Set the delay before the first retry.
+  ///
+  pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+    self.initial_backoff = initial_backoff;
+    self
+  }
+
+  /// Set the maximum delay between retries.
+  ///
+  pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+    self.max_backoff = max_backoff;
+    self
+  }
+
+  /// Set the factor the delay is multiplied by after each failed attempt.
+  ///
+  pub fn multiplier(mut self, multiplier: f64) -> Self {
+    self.multiplier = multiplier;
+    self
+  }
+
+  /// Compute the delay following the specified one.
+  ///
+  fn next_backoff(&self, backoff: Duration) -> Duration {
+    backoff.mul_f64(self.multiplier.max(1.0)).min(self.max_backoff)
+  }
+}
+
+/// The outcome of a successfully retried transaction.
+///
+#[derive(Debug)]
+pub struct PQRetryOutcome<T> {
+  /// The value returned by the last (successful) attempt.
+  pub value: T,
+  /// The number of attempts made (`1` when no retry was needed).
+  pub attempts: usize,
+}
+
+/* ========================================================================== *
+ * RETRY                                                                      *
+ * ========================================================================== */
+
+/// Returns `true` if the specified [`PQError`] carries a SQLSTATE indicating
+/// a transient failure (serialization failure or deadlock).
+///
+pub fn is_transient(error: &PQError) -> bool {
+  match &error.sqlstate {
+    Some(sqlstate) => sqlstate == SERIALIZATION_FAILURE || sqlstate == DEADLOCK_DETECTED,
+    None => false,
+  }
+}
+
+/// Run the specified closure in a transaction, retrying it (with a new
+/// transaction) whenever it fails with a serialization failure or deadlock,
+/// or when the connection to the server is lost _before_ committing.
+///
+/// Failures while committing are only retried when the server reported a
+/// transient SQLSTATE, as after a connection loss the outcome of the commit
+/// is unknown.
+///
+/// When the transaction was retried, the message of the last error notes how
+/// many attempts were made.
+///
+pub fn retry_transaction<T, F>(
+  connection: &PQConnection,
+  options: PQTransactionOptions,
+  retry: PQRetryOptions,
+  mut closure: F,
+) -> PQResult<PQRetryOutcome<T>>
+where
+  F: FnMut(&PQTransaction) -> PQResult<T>,
+{
+  let mut backoff = retry.initial_backoff;
+  let mut attempts = 0;
+
+  loop {
+    attempts += 1;
+
+    let (error, retryable) = match attempt(connection, options, &mut closure) {
+      Ok(value) => return Ok(PQRetryOutcome { value, attempts }),
+      Err(failure) => failure,
+    };
+
+    if (! retryable) || (attempts >= retry.max_attempts) {
+      return Err(match attempts {
+        1 => error,
+        _ => PQError {
+          message: format!("{} (after {} attempts)", error.message, attempts),
+          ..error
+        },
+      });
+    }
+
+    debug!("Retrying transaction (attempt {}) in {:?}: {}", attempts, backoff, error);
+    sleep(backoff);
+    backoff = retry.next_backoff(backoff);
+
+    if connection.pq_status() == PQConnectionStatus::Bad {
+      // If resetting fails, the next attempt will simply fail too
+      if let Err(error) = connection.pq_reset() {
+        debug!("Unable to reset connection: {}", error);
+      }
+    }
+  }
+}
+
+/// Run a single attempt of a transaction, returning the error and whether it
+/// can be retried on failure.
+///
+fn attempt<T, F>(
+  connection: &PQConnection,
+  options: PQTransactionOptions,
+  closure: &mut F,
+) -> Result<T, (PQError, bool)>
+where
+  F: FnMut(&PQTransaction) -> PQResult<T>,
+{
+  let connection_lost = || connection.pq_status() == PQConnectionStatus::Bad;
+
+  let transaction = PQTransaction::begin(connection, options)
+    .map_err(|error| (error, connection_lost()))?;
+
+  let value = match closure(&transaction) {
+    Ok(value) => value,
+    Err(error) => {
+      let retryable = is_transient(&error) || connection_lost();
+      return Err((error, retryable));
+    },
+  };
+
+  transaction.commit()
+    .map_err(|error| {
+      let retryable = is_transient(&error);
+      (error, retryable)
+    })?;
+
+  Ok(value)
+}
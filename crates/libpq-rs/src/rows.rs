@@ -0,0 +1,316 @@
+//! Streaming of large results, one row at a time.
+
+use crate::connection::PQConnection;
+use crate::debug::*;
+use crate::errors::*;
+use crate::response::PQResponse;
+use crate::response::PQResponseStatus;
+use crate::response::PQRow;
+
+/// An [`Iterator`] over the rows of a (potentially huge) result, sent by the
+/// server one at a time in _single-row mode_.
+///
+/// Each row is yielded as a [`PQResponse`] with a [`PQResponseStatus::SingleTuple`]
+/// status and exactly one tuple. Rows are only read from the connection when
+/// the next one is requested, so a slow consumer leaves data in the socket
+/// and (eventually) makes the server wait.
+///
+/// Once all rows are read, the final (zero rows) [`PQResponseStatus::TuplesOk`]
+/// result is available from [`PQRowStream::completion`], while a command
+/// failing midway yields an error as its last item.
+///
+/// Dropping the stream before it ends cancels the command and discards its
+/// remaining results.
+///
+/// A memory budget can be [set][PQRowStream::with_limit] on the stream
+/// (defaulting to the connection's [result size limit][PQConnection::result_size_limit]):
+/// should the total size of all rows read exceed it, the command is canceled
+/// and an error of kind [`PQErrorKind::ResultTooLarge`] is yielded.
+///
+/// LibPQ 17 introduced _chunked-rows mode_, returning up to N rows at once:
+/// as the `pq-sys` bindings lack both `PQsetChunkedRowsMode` and the
+/// `PGRES_TUPLES_CHUNK` status, single-row mode is always used.
+///
+/// See [Retrieving Query Results in Chunks](https://www.postgresql.org/docs/current/libpq-single-row-mode.html)
+///
+pub struct PQRowStream<'a> {
+  id: usize,
+  connection: &'a PQConnection,
+  completion: Option<PQResponse>,
+  limit: Option<usize>,
+  size: usize,
+  finished: bool,
+}
+
+debug_self!(PQRowStream<'_>, id);
+
+impl Drop for PQRowStream<'_> {
+  fn drop(&mut self) {
+    debug_drop!(self);
+
+    if ! self.finished {
+      debug!("Canceling unfinished row stream {:?}", self);
+      self.cancel();
+    }
+  }
+}
+
+impl <'a> PQRowStream<'a> {
+  /// Send a command (with optional parameters) selecting single-row mode
+  /// for its results.
+  ///
+  pub fn new(connection: &'a PQConnection, command: String, params: Option<Vec<String>>) -> PQResult<Self> {
+    match params {
+      Some(params) => connection.pq_send_query_params(command, params)?,
+      None => connection.pq_send_query(command)?,
+    }
+
+    let stream = debug_create!(Self {
+      id: debug_id(),
+      connection,
+      completion: None,
+      limit: connection.result_size_limit(),
+      size: 0,
+      finished: false,
+    });
+
+    match connection.pq_set_single_row_mode() {
+      true => Ok(stream),
+      false => Err("Unable to select single-row mode".into()),
+    }
+  }
+
+  /// Return the [`PQConnection`] this stream reads from.
+  ///
+  pub fn connection(&self) -> &PQConnection {
+    self.connection
+  }
+
+  /// Return the final result of the command, once all rows were read.
+  ///
+  pub fn completion(&self) -> Option<&PQResponse> {
+    self.completion.as_ref()
+  }
+
+  /// Set the memory budget (in bytes) for all rows read by this stream, or
+  /// `None` for no limit.
+  ///
+  /// See [`PQresultMemorySize`](https://www.postgresql.org/docs/current/libpq-misc.html#LIBPQ-PQRESULTMEMORYSIZE)
+  ///
+  pub fn with_limit(mut self, limit: Option<usize>) -> Self {
+    self.limit = limit;
+    self
+  }
+
+  /// Return the total size (in bytes) of all rows read so far.
+  ///
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  /// Read all remaining results (after an error) until the command is done.
+  ///
+  fn drain(&mut self) {
+    while self.connection.pq_get_result().is_some() {}
+    self.finished = true;
+  }
+
+  /// Cancel the command, and discard all its remaining results.
+  ///
+  fn cancel(&mut self) {
+    cancel(self.connection);
+    self.finished = true;
+  }
+
+  /// Account for the size of a row, canceling the command if over budget.
+  ///
+  fn account(&mut self, response: &PQResponse) -> PQResult<()> {
+    self.size += response.pq_result_memory_size();
+
+    match self.limit {
+      Some(limit) if self.size > limit => {
+        debug!("Row stream {:?} exceeded its limit of {} bytes", self, limit);
+        self.cancel();
+        Err(too_large(limit))
+      },
+      _ => Ok(()),
+    }
+  }
+}
+
+impl Iterator for PQRowStream<'_> {
+  type Item = PQResult<PQResponse>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    while ! self.finished {
+      let response = match self.connection.pq_get_result() {
+        Some(response) => response,
+        None => {
+          self.finished = true;
+          break;
+        },
+      };
+
+      match response.pq_result_status() {
+        PQResponseStatus::SingleTuple => return Some(self.account(&response).map(|_| response)),
+        _ => match response.into_result() {
+          Ok(response) => self.completion = Some(response),
+          Err(error) => {
+            self.drain();
+            return Some(Err(error));
+          },
+        },
+      }
+    }
+
+    None
+  }
+}
+
+/// Execute a command (with optional parameters) in single-row mode, collecting
+/// all its rows as long as their total size does not exceed the specified
+/// limit.
+///
+/// See [`PQRowStream`]
+///
+pub fn exec_with_limit(
+  connection: &PQConnection,
+  command: String,
+  params: Option<Vec<String>>,
+  limit: usize,
+) -> PQResult<Vec<PQRow>> {
+  let mut rows = Vec::<PQRow>::new();
+
+  for response in PQRowStream::new(connection, command, params)?.with_limit(Some(limit)) {
+    rows.extend(Vec::<PQRow>::from(response?));
+  }
+
+  Ok(rows)
+}
+
+/* ========================================================================== *
+ * RESULT ASSEMBLER                                                           *
+ * ========================================================================== */
+
+/// Reassembles the rows of a command read in single-row mode into whole
+/// results (one per statement), as long as their total size does not exceed
+/// the connection's [result size limit][PQConnection::result_size_limit].
+///
+/// Without a limit, single-row mode is not selected and all results are
+/// simply passed through.
+///
+pub(crate) struct PQResultAssembler {
+  limit: Option<usize>,
+  pending: Option<PQResponse>,
+  exceeded: bool,
+}
+
+impl PQResultAssembler {
+  /// Create a new [`PQResultAssembler`] for the command just sent, selecting
+  /// single-row mode if the connection has a result size limit.
+  ///
+  pub(crate) fn start(connection: &PQConnection) -> PQResult<Self> {
+    let limit = connection.result_size_limit();
+
+    if limit.is_some() && ! connection.pq_set_single_row_mode() {
+      return Err("Unable to select single-row mode".into());
+    }
+
+    Ok(Self { limit, pending: None, exceeded: false })
+  }
+
+  /// Process the next result of the command, returning it once complete.
+  ///
+  /// Once the limit is exceeded an error of kind [`PQErrorKind::ResultTooLarge`]
+  /// is returned (only once): the command should then be canceled, and all
+  /// rows received afterwards are discarded.
+  ///
+  pub(crate) fn push(&mut self, response: PQResponse) -> PQResult<Option<PQResponse>> {
+    if response.pq_result_status() != PQResponseStatus::SingleTuple {
+      return self.complete(response).map(Some);
+    }
+
+    if self.exceeded { return Ok(None) }
+
+    let mut pending = match self.pending.take() {
+      Some(pending) => pending,
+      None => response.copy_columns()?,
+    };
+
+    pending.append_rows(&response)?;
+
+    match self.limit {
+      Some(limit) if pending.pq_result_memory_size() > limit => {
+        self.exceeded = true;
+        Err(too_large(limit))
+      },
+      _ => {
+        self.pending = Some(pending);
+        Ok(None)
+      },
+    }
+  }
+
+  /// Append all rows received so far to the final result of a statement.
+  ///
+  fn complete(&mut self, mut response: PQResponse) -> PQResult<PQResponse> {
+    if let Some(pending) = self.pending.take() {
+      if response.pq_result_status() == PQResponseStatus::TuplesOk {
+        response.append_rows(&pending)?;
+      }
+    }
+
+    Ok(response)
+  }
+}
+
+/// Execute a command in single-row mode, returning its last result as
+/// [`PQConnection::pq_exec`] does, once reassembled by a [`PQResultAssembler`].
+///
+pub(crate) fn exec_assembled(connection: &PQConnection, command: String) -> PQResult<PQResponse> {
+  connection.pq_send_query(command)?;
+
+  let mut assembler = PQResultAssembler::start(connection)?;
+  let mut last = None;
+
+  while let Some(response) = connection.pq_get_result() {
+    let status = response.pq_result_status();
+
+    match assembler.push(response) {
+      Ok(Some(response)) => last = Some(response),
+      Ok(None) => (),
+      Err(error) => {
+        debug!("Result exceeded size limit, canceling command on {:?}", connection);
+        cancel(connection);
+        return Err(error);
+      },
+    }
+
+    // Same as "PQexec", stop as soon as a "COPY" starts
+    match status {
+      PQResponseStatus::CopyIn |
+      PQResponseStatus::CopyOut |
+      PQResponseStatus::CopyBoth => break,
+      _ => (),
+    }
+  }
+
+  last.ok_or_else(|| PQError::from(connection))
+}
+
+/// Cancel the command running on a connection, and discard all its remaining
+/// results.
+///
+fn cancel(connection: &PQConnection) {
+  if let Ok(cancel) = connection.pq_get_cancel() {
+    let _ = cancel.pq_cancel();
+  }
+  while connection.pq_get_result().is_some() {}
+}
+
+/// Return the error for results exceeding the specified limit.
+///
+fn too_large(limit: usize) -> PQError {
+  PQError::from(format!("Result exceeded its size limit of {} bytes", limit))
+    .with_kind(PQErrorKind::ResultTooLarge)
+}
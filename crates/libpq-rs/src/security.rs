@@ -0,0 +1,237 @@
+//! Security policies enforced on connections, at connect time and after
+//! each reset.
+
+use crate::connection::PQConnection;
+use crate::conninfo::PQConninfo;
+use crate::errors::*;
+
+/// Values of `sslmode` requiring SSL.
+static SSL_REQUIRED_MODES: [&str; 3] = [ "require", "verify-ca", "verify-full" ];
+
+/// A TLS protocol version.
+///
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PQTlsVersion {
+  /// TLS 1.0 (`TLSv1`).
+  Tls1_0,
+  /// TLS 1.1 (`TLSv1.1`).
+  Tls1_1,
+  /// TLS 1.2 (`TLSv1.2`).
+  Tls1_2,
+  /// TLS 1.3 (`TLSv1.3`).
+  Tls1_3,
+}
+
+impl TryFrom<&str> for PQTlsVersion {
+  type Error = PQError;
+
+  /// Parse a TLS protocol version, as reported by the `protocol` SSL
+  /// attribute or used by `ssl_min_protocol_version`.
+  ///
+  fn try_from(value: &str) -> PQResult<Self> {
+    match value {
+      "TLSv1" | "TLSv1.0" => Ok(Self::Tls1_0),
+      "TLSv1.1" => Ok(Self::Tls1_1),
+      "TLSv1.2" => Ok(Self::Tls1_2),
+      "TLSv1.3" => Ok(Self::Tls1_3),
+      _ => Err(format!("Unknown TLS protocol version \"{}\"", value).into()),
+    }
+  }
+}
+
+impl PQTlsVersion {
+  /// Returns the name of this version, as used by `ssl_min_protocol_version`.
+  ///
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Tls1_0 => "TLSv1",
+      Self::Tls1_1 => "TLSv1.1",
+      Self::Tls1_2 => "TLSv1.2",
+      Self::Tls1_3 => "TLSv1.3",
+    }
+  }
+}
+
+/* ========================================================================== *
+ * POLICY                                                                     *
+ * ========================================================================== */
+
+/// A security policy every connection must satisfy.
+///
+/// Policies are enforced in two steps: first they are [applied][PQSecurityPolicy::apply]
+/// to the connection options, so that LibPQ itself refuses insecure servers
+/// _before_ sending any credentials, then they are [checked][PQSecurityPolicy::check]
+/// against the established connection, failing it with a descriptive error
+/// of kind [`PQErrorKind::SecurityPolicy`] when violated.
+///
+/// The default policy enforces nothing.
+///
+/// See [`PQConnection::connect_with_policy`]
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PQSecurityPolicy {
+  /// Require connections to use SSL.
+  pub require_ssl: bool,
+  /// The minimum TLS protocol version allowed (implies SSL).
+  pub min_tls_version: Option<PQTlsVersion>,
+  /// The only ciphers allowed (implies SSL), or `None` for any cipher.
+  pub allowed_ciphers: Option<Vec<String>>,
+  /// Require SCRAM channel binding (`channel_binding=require`).
+  pub require_channel_binding: bool,
+  /// The authentication methods the server must request (`require_auth`).
+  pub require_auth: Option<String>,
+  /// Require authentication to use a password (refusing `trust`).
+  pub require_password: bool,
+}
+
+impl PQSecurityPolicy {
+  /// Require (or not) connections to use SSL.
+  ///
+  pub fn require_ssl(mut self, require_ssl: bool) -> Self {
+    self.require_ssl = require_ssl;
+    self
+  }
+
+  /// Set the minimum TLS protocol version allowed.
+  ///
+  pub fn min_tls_version(mut self, min_tls_version: Option<PQTlsVersion>) -> Self {
+    self.min_tls_version = min_tls_version;
+    self
+  }
+
+  /// Set the only ciphers allowed.
+  ///
+  pub fn allowed_ciphers<S: AsRef<str>>(mut self, allowed_ciphers: Option<&[S]>) -> Self {
+    self.allowed_ciphers = allowed_ciphers.map(|ciphers| ciphers
+      .iter()
+      .map(|cipher| cipher.as_ref().to_string())
+      .collect());
+    self
+  }
+
+  /// Require (or not) SCRAM channel binding.
+  ///
+  pub fn require_channel_binding(mut self, require_channel_binding: bool) -> Self {
+    self.require_channel_binding = require_channel_binding;
+    self
+  }
+
+  /// Set the authentication methods the server must request, in the format
+  /// of LibPQ's `require_auth` option (e.g. `scram-sha-256`).
+  ///
+  /// See [`require_auth`](https://www.postgresql.org/docs/current/libpq-connect.html#LIBPQ-CONNECT-REQUIRE-AUTH)
+  ///
+  pub fn require_auth(mut self, require_auth: Option<&str>) -> Self {
+    self.require_auth = require_auth.map(str::to_string);
+    self
+  }
+
+  /// Require (or not) authentication to use a password.
+  ///
+  pub fn require_password(mut self, require_password: bool) -> Self {
+    self.require_password = require_password;
+    self
+  }
+
+  /// Returns `true` if this policy requires SSL (explicitly, or implicitly
+  /// by restricting TLS versions or ciphers).
+  ///
+  pub fn requires_ssl(&self) -> bool {
+    self.require_ssl || self.min_tls_version.is_some() || self.allowed_ciphers.is_some()
+  }
+
+  /// Return a copy of the specified [`PQConninfo`], tightened so that LibPQ
+  /// enforces as much of this policy as possible while connecting.
+  ///
+  /// This sets `sslmode` (unless already requiring SSL),
+  /// `ssl_min_protocol_version` (unless already stricter), `channel_binding`
+  /// and `require_auth`.
+  ///
+  pub fn apply(&self, info: &PQConninfo) -> PQConninfo {
+    let mut overrides = Vec::<(&str, &str)>::new();
+
+    if self.requires_ssl() && ! info.get("sslmode").is_some_and(|mode| SSL_REQUIRED_MODES.contains(&mode)) {
+      overrides.push(("sslmode", "require"));
+    }
+
+    if let Some(min) = self.min_tls_version {
+      let current = info.get("ssl_min_protocol_version").and_then(|value| PQTlsVersion::try_from(value).ok());
+      if current.is_none_or(|current| current < min) {
+        overrides.push(("ssl_min_protocol_version", min.as_str()));
+      }
+    }
+
+    if self.require_channel_binding {
+      overrides.push(("channel_binding", "require"));
+    }
+
+    if let Some(require_auth) = &self.require_auth {
+      overrides.push(("require_auth", require_auth));
+    }
+
+    info.iter()
+      .filter(|(key, _)| ! overrides.iter().any(|(k, _)| k == key))
+      .cloned()
+      .chain(overrides.iter().map(|(key, value)| (key.to_string(), value.to_string())))
+      .collect()
+  }
+
+  /// Check this policy against an established connection, returning an
+  /// error (of kind [`PQErrorKind::SecurityPolicy`]) describing all its
+  /// violations, if any.
+  ///
+  pub fn check(&self, connection: &PQConnection) -> PQResult<()> {
+    let mut violations = Vec::<String>::new();
+    let ssl_in_use = connection.pq_ssl_in_use();
+
+    if self.requires_ssl() && ! ssl_in_use {
+      violations.push("SSL is not in use".to_string());
+    }
+
+    if ssl_in_use && (self.min_tls_version.is_some() || self.allowed_ciphers.is_some()) {
+      let attributes = connection.pq_ssl_attributes()?;
+      let attribute = |name: &str| attributes
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str());
+
+      if let Some(min) = self.min_tls_version {
+        let protocol = attribute("protocol").unwrap_or("unknown");
+        if ! PQTlsVersion::try_from(protocol).is_ok_and(|version| version >= min) {
+          violations.push(format!("TLS protocol {} is older than {}", protocol, min.as_str()));
+        }
+      }
+
+      if let Some(allowed) = &self.allowed_ciphers {
+        let cipher = attribute("cipher").unwrap_or("unknown");
+        if ! allowed.iter().any(|allowed| allowed == cipher) {
+          violations.push(format!("cipher {} is not allowed", cipher));
+        }
+      }
+    }
+
+    if self.require_channel_binding || self.require_auth.is_some() {
+      let info = connection.pq_conninfo()?;
+
+      if self.require_channel_binding && info.get("channel_binding") != Some("require") {
+        violations.push("channel binding is not required".to_string());
+      }
+
+      if let Some(require_auth) = &self.require_auth {
+        if info.get("require_auth") != Some(require_auth.as_str()) {
+          violations.push(format!("authentication is not restricted to {}", require_auth));
+        }
+      }
+    }
+
+    if self.require_password && ! connection.pq_connection_used_password() {
+      violations.push("authentication did not use a password".to_string());
+    }
+
+    match violations.is_empty() {
+      true => Ok(()),
+      false => Err(PQError::from(format!("Connection violates security policy: {}", violations.join(", ")))
+        .with_kind(PQErrorKind::SecurityPolicy)),
+    }
+  }
+}
@@ -0,0 +1,272 @@
+//! Session state tracking, and reset of "dirty" sessions.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+/// The parameters reported by the server via `ParameterStatus` messages.
+///
+/// See [`PQparameterStatus`](https://www.postgresql.org/docs/current/libpq-status.html#LIBPQ-PQPARAMETERSTATUS)
+///
+pub static REPORTED_PARAMETERS: [&str; 15] = [
+  "application_name",
+  "client_encoding",
+  "DateStyle",
+  "default_transaction_read_only",
+  "in_hot_standby",
+  "integer_datetimes",
+  "IntervalStyle",
+  "is_superuser",
+  "scram_iterations",
+  "search_path",
+  "server_encoding",
+  "server_version",
+  "session_authorization",
+  "standard_conforming_strings",
+  "TimeZone",
+];
+
+/// Leading keywords of statements changing the session state.
+static SESSION_KEYWORDS: [&str; 6] = [
+  "SET", "RESET", "LISTEN", "PREPARE", "DECLARE", "LOAD",
+];
+
+/// Function calls changing the session state, wherever they appear.
+static SESSION_FUNCTIONS: [&str; 3] = [
+  "SET_CONFIG", "PG_ADVISORY_LOCK", "PG_TRY_ADVISORY_LOCK",
+];
+
+/* ========================================================================== *
+ * SESSION RESET                                                              *
+ * ========================================================================== */
+
+/// How to reset the state of a session before reusing its connection.
+///
+#[derive(Debug, Clone, Default, Hash, PartialEq, Eq)]
+pub enum PQSessionReset {
+  /// Discard _all_ session state (`DISCARD ALL`).
+  #[default]
+  DiscardAll,
+  /// Only restore run-time parameters to their defaults (`RESET ALL`).
+  ResetAll,
+  /// Run custom SQL to reset the session.
+  Custom(String),
+}
+
+impl PQSessionReset {
+  /// Returns the SQL command used to reset the session.
+  ///
+  /// See [`DISCARD`](https://www.postgresql.org/docs/current/sql-discard.html)
+  /// See [`RESET`](https://www.postgresql.org/docs/current/sql-reset.html)
+  ///
+  pub fn to_command(&self) -> String {
+    match self {
+      Self::DiscardAll => "DISCARD ALL".to_string(),
+      Self::ResetAll => "RESET ALL".to_string(),
+      Self::Custom(command) => command.clone(),
+    }
+  }
+}
+
+/* ========================================================================== *
+ * SESSION TRACKER                                                            *
+ * ========================================================================== */
+
+/// Returns `true` if the specified command _might_ change the session state
+/// (run-time parameters, temporary tables, prepared statements, cursors,
+/// `LISTEN`s or advisory locks).
+///
+/// This errs on the side of caution: multiple statements are split on `;`
+/// regardless of quoting, and function names are matched anywhere. Comments
+/// (`-- ...` and, possibly nested, `/* ... */`) outside of quoted literals
+/// and identifiers are ignored, so they can't hide a leading keyword.
+///
+pub fn changes_session_state(command: &str) -> bool {
+  let command = strip_comments(command).to_uppercase();
+
+  if SESSION_FUNCTIONS.iter().any(|function| command.contains(function)) {
+    return true;
+  }
+
+  command.split(';').any(|statement| {
+    let words = statement
+      .split_whitespace()
+      .take(4)
+      .collect::<Vec<_>>();
+
+    match words.as_slice() {
+      ["CREATE", rest @ ..] => rest.contains(&"TEMP") || rest.contains(&"TEMPORARY"),
+      ["PREPARE", "TRANSACTION", ..] => false,
+      ["SET", "LOCAL" | "TRANSACTION", ..] => false,
+      [keyword, ..] => SESSION_KEYWORDS.contains(keyword),
+      [] => false,
+    }
+  })
+}
+
+/// Replace all comments in the specified command with a single space.
+///
+/// Dollar-quoted strings are not recognized, hence comment markers within
+/// them might strip part of their body (and possibly what follows).
+///
+fn strip_comments(command: &str) -> String {
+  let mut stripped = String::with_capacity(command.len());
+  let mut chars = command.chars().peekable();
+  let mut quote: Option<char> = None;
+
+  while let Some(char) = chars.next() {
+    match (quote, char, chars.peek()) {
+      // Within a literal or identifier only look for its end (doubled
+      // quotes simply close and re-open it)
+      (Some(q), c, _) => {
+        if c == q { quote = None }
+        stripped.push(c);
+      },
+      (None, '\'' | '"', _) => {
+        quote = Some(char);
+        stripped.push(char);
+      },
+      (None, '-', Some('-')) => {
+        for c in chars.by_ref() {
+          if c == '\n' { break }
+        }
+        stripped.push(' ');
+      },
+      (None, '/', Some('*')) => {
+        chars.next();
+        let mut depth = 1;
+        while depth > 0 {
+          match (chars.next(), chars.peek()) {
+            (Some('*'), Some('/')) => { chars.next(); depth -= 1 },
+            (Some('/'), Some('*')) => { chars.next(); depth += 1 },
+            (Some(_), _) => continue,
+            (None, _) => break,
+          }
+        }
+        stripped.push(' ');
+      },
+      (None, c, _) => stripped.push(c),
+    }
+  }
+
+  stripped
+}
+
+/// Track changes to the state of a session.
+///
+/// The tracker keeps a _baseline_ of all [reported parameters][REPORTED_PARAMETERS]
+/// taken when the session was (re)established, plus a flag marking commands
+/// that might have changed the session state.
+///
+#[derive(Debug)]
+pub(crate) struct PQSessionTracker {
+  dirty: AtomicBool,
+  baseline: Mutex<Vec<(String, Option<String>)>>,
+}
+
+impl PQSessionTracker {
+  /// Create a new, _clean_, [`PQSessionTracker`] with an empty baseline.
+  ///
+  pub(crate) fn new() -> Self {
+    Self { dirty: AtomicBool::new(false), baseline: Mutex::new(Vec::new()) }
+  }
+
+  /// Mark the session dirty if the command specified might change its state.
+  ///
+  pub(crate) fn track(&self, command: &str) {
+    if changes_session_state(command) {
+      self.mark_dirty();
+    }
+  }
+
+  /// Explicitly mark the session as dirty.
+  ///
+  pub(crate) fn mark_dirty(&self) {
+    self.dirty.store(true, Ordering::Relaxed);
+  }
+
+  /// Returns `true` if the session was marked as dirty.
+  ///
+  pub(crate) fn is_marked_dirty(&self) -> bool {
+    self.dirty.load(Ordering::Relaxed)
+  }
+
+  /// Returns `true` if the parameters specified differ from our baseline.
+  ///
+  pub(crate) fn parameters_changed(&self, parameters: &[(String, Option<String>)]) -> bool {
+    let baseline = self.baseline.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    baseline.as_slice() != parameters
+  }
+
+  /// Set a new baseline for the session, marking it as clean.
+  ///
+  pub(crate) fn reset(&self, parameters: Vec<(String, Option<String>)>) {
+    let mut baseline = self.baseline.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *baseline = parameters;
+    self.dirty.store(false, Ordering::Relaxed);
+  }
+}
+
+/* ========================================================================== *
+ * TESTS                                                                      *
+ * ========================================================================== */
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn session_state_statements() {
+    assert!(changes_session_state("SET search_path TO public"));
+    assert!(changes_session_state("set  statement_timeout = 0"));
+    assert!(!changes_session_state("SET LOCAL search_path TO public"));
+    assert!(!changes_session_state("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE"));
+    assert!(changes_session_state("PREPARE stmt AS SELECT 1"));
+    assert!(!changes_session_state("PREPARE TRANSACTION 'gid'"));
+    assert!(changes_session_state("CREATE TEMP TABLE t (i int)"));
+    assert!(changes_session_state("CREATE TEMPORARY TABLE t (i int)"));
+    assert!(!changes_session_state("CREATE TABLE t (i int)"));
+    assert!(changes_session_state("LISTEN channel"));
+    assert!(changes_session_state("DECLARE c CURSOR WITH HOLD FOR SELECT 1"));
+    assert!(!changes_session_state("SELECT 1"));
+    assert!(!changes_session_state(""));
+  }
+
+  #[test]
+  fn session_state_functions() {
+    assert!(changes_session_state("SELECT set_config('search_path', 'public', false)"));
+    assert!(changes_session_state("SELECT pg_advisory_lock(1)"));
+    assert!(changes_session_state("SELECT pg_try_advisory_lock(1)"));
+    assert!(!changes_session_state("SELECT current_setting('search_path')"));
+  }
+
+  #[test]
+  fn session_state_multiple_statements() {
+    assert!(changes_session_state("SELECT 1; SET search_path TO public"));
+    assert!(changes_session_state("BEGIN; SET LOCAL work_mem = '1MB'; RESET ALL; COMMIT"));
+    assert!(!changes_session_state("BEGIN; SET LOCAL work_mem = '1MB'; COMMIT"));
+    // Statements are split on ";" even within literals (erring on caution)
+    assert!(changes_session_state("SELECT 'a;set b'"));
+  }
+
+  #[test]
+  fn session_state_comments() {
+    assert!(changes_session_state("-- comment\nSET search_path TO public"));
+    assert!(changes_session_state("/* comment */ SET search_path TO public"));
+    assert!(changes_session_state("/* outer /* nested */ still */ SET x = 1"));
+    assert!(changes_session_state("SELECT 1; -- comment\n SET x = 1"));
+    assert!(!changes_session_state("-- SET x = 1\nSELECT 1"));
+    assert!(!changes_session_state("/* SET x = 1 */ SELECT 1"));
+    // Comment markers within literals and identifiers are not comments
+    assert!(changes_session_state("SELECT '--'; SET x = 1"));
+    assert!(changes_session_state("SELECT 1 AS \"/*\"; SET x = 1"));
+  }
+
+  #[test]
+  fn comments_stripping() {
+    assert_eq!(strip_comments("SELECT 1 -- one\n, 2"), "SELECT 1  , 2");
+    assert_eq!(strip_comments("SELECT /* a /* b */ c */ 1"), "SELECT   1");
+    assert_eq!(strip_comments("SELECT 'it''s -- not' /* unterminated"), "SELECT 'it''s -- not'  ");
+    assert_eq!(strip_comments("SELECT 8 - -1"), "SELECT 8 - -1");
+  }
+}
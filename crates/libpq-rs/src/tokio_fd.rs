@@ -0,0 +1,98 @@
+//! Adapters driving nonblocking connections with tokio's [`AsyncFd`].
+
+use crate::connection::PQConnection;
+use crate::connection::PQNextResult;
+use crate::connection::PQPollingInterest;
+use crate::debug::*;
+use crate::errors::*;
+use crate::response::PQResponse;
+use std::os::fd::RawFd;
+use tokio::io::unix::AsyncFd;
+
+/// A [`PQConnection`] whose socket is registered with tokio's reactor.
+///
+/// This must be created (and used) from within a tokio runtime with IO
+/// enabled. As resetting a connection might change its socket, the
+/// connection must be [unwrapped][PQTokioConnection::into_inner] before
+/// being reset, and wrapped again afterwards.
+///
+pub struct PQTokioConnection {
+  id: usize,
+  // Fields drop in order: deregister our socket _before_ closing it
+  socket: AsyncFd<RawFd>,
+  connection: PQConnection,
+}
+
+debug_self!(PQTokioConnection, id);
+
+impl TryFrom<PQConnection> for PQTokioConnection {
+  type Error = PQError;
+
+  /// Wrap a [`PQConnection`], switching it to nonblocking mode.
+  ///
+  fn try_from(connection: PQConnection) -> PQResult<Self> {
+    connection.pq_setnonblocking(true)?;
+
+    // The socket stays open (and unchanged) as long as we own the connection
+    let socket = unsafe { AsyncFd::register(connection.pq_socket()) }
+      .map_err(| err | format!("Error registering socket: {}", err))?;
+
+    Ok(debug_create!(Self { id: debug_id(), socket, connection }))
+  }
+}
+
+impl PQTokioConnection {
+  /// Return the wrapped [`PQConnection`].
+  ///
+  pub fn connection(&self) -> &PQConnection {
+    &self.connection
+  }
+
+  /// Deregister the socket from tokio's reactor, returning the [`PQConnection`].
+  ///
+  pub fn into_inner(self) -> PQConnection {
+    let Self { socket, connection, .. } = self;
+    socket.into_inner();
+    connection
+  }
+
+  /// Send a command, and collect all its [`PQResponse`]s.
+  ///
+  pub async fn query(&self, command: String) -> PQResult<Vec<PQResponse>> {
+    self.connection.pq_send_query(command)?;
+    self.collect().await
+  }
+
+  /// Send a command with parameters, and collect all its [`PQResponse`]s.
+  ///
+  pub async fn query_params(&self, command: String, params: Vec<String>) -> PQResult<Vec<PQResponse>> {
+    self.connection.pq_send_query_params(command, params)?;
+    self.collect().await
+  }
+
+  /// Flush our output and collect all results, waiting on tokio's reactor.
+  ///
+  async fn collect(&self) -> PQResult<Vec<PQResponse>> {
+    let mut responses = Vec::<PQResponse>::new();
+
+    loop {
+      let interest = match self.connection.try_get_result()? {
+        PQNextResult::Ready(response) => {
+          responses.push(response);
+          continue;
+        },
+        PQNextResult::Done => return Ok(responses),
+        PQNextResult::Wait(interest) => interest,
+      };
+
+      // Readiness is cleared _before_ trying again: anything arriving after
+      // this point will either be consumed, or make the socket ready again
+      let mut guard = match interest {
+        PQPollingInterest::Readable => self.socket.readable().await,
+        PQPollingInterest::Writable => self.socket.writable().await,
+      }.map_err(| err | format!("Error waiting on socket: {}", err))?;
+
+      guard.clear_ready();
+    }
+  }
+}
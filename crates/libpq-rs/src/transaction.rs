@@ -0,0 +1,344 @@
+//! Transactions and savepoints.
+
+use crate::connection::PQConnection;
+use crate::connection::PQTransactionStatus;
+use crate::cursor::PQCursor;
+use crate::cursor::PQCursorOptions;
+use crate::debug::*;
+use crate::errors::*;
+use crate::large_object::PQLargeObject;
+use crate::large_object::PQLargeObjectMode;
+use crate::response::PQResponse;
+use crate::twophase::PQGlobalTransactionId;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+/* ========================================================================== *
+ * OPTIONS                                                                    *
+ * ========================================================================== */
+
+/// The isolation level of a transaction.
+///
+/// See [Transaction Isolation](https://www.postgresql.org/docs/current/transaction-iso.html)
+///
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub enum PQIsolationLevel {
+  /// Read uncommitted (behaves like `READ COMMITTED` in PostgreSQL).
+  ReadUncommitted = 0,
+  /// A statement can only see rows committed before it began.
+  ReadCommitted = 1,
+  /// All statements of the current transaction can only see rows committed
+  /// before the first query or data-modification statement was executed.
+  RepeatableRead = 2,
+  /// Like `RepeatableRead`, but guaranteeing serializable execution.
+  Serializable = 3,
+}
+
+impl PQIsolationLevel {
+  /// Returns the SQL representation of this isolation level.
+  ///
+  pub fn as_sql(&self) -> &'static str {
+    match self {
+      Self::ReadUncommitted => "READ UNCOMMITTED",
+      Self::ReadCommitted => "READ COMMITTED",
+      Self::RepeatableRead => "REPEATABLE READ",
+      Self::Serializable => "SERIALIZABLE",
+    }
+  }
+}
+
+/// Options used when starting a new [`PQTransaction`].
+///
+/// Any option left unset will use the server's defaults.
+///
+/// See [`BEGIN`](https://www.postgresql.org/docs/current/sql-begin.html)
+///
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PQTransactionOptions {
+  pub isolation_level: Option<PQIsolationLevel>,
+  pub read_only: Option<bool>,
+  pub deferrable: Option<bool>,
+}
+
+impl PQTransactionOptions {
+  /// Set the isolation level of the transaction.
+  ///
+  pub fn isolation_level(mut self, isolation_level: PQIsolationLevel) -> Self {
+    self.isolation_level = Some(isolation_level);
+    self
+  }
+
+  /// Set the transaction access mode (`READ ONLY` or `READ WRITE`).
+  ///
+  pub fn read_only(mut self, read_only: bool) -> Self {
+    self.read_only = Some(read_only);
+    self
+  }
+
+  /// Set the transaction deferrable mode (`DEFERRABLE` or `NOT DEFERRABLE`).
+  ///
+  /// This only has effect on `SERIALIZABLE` and `READ ONLY` transactions.
+  ///
+  pub fn deferrable(mut self, deferrable: bool) -> Self {
+    self.deferrable = Some(deferrable);
+    self
+  }
+
+  /// Returns the `BEGIN` command for these options.
+  ///
+  pub fn to_begin_command(&self) -> String {
+    let mut modes = Vec::<&str>::new();
+
+    let isolation_level = self.isolation_level
+      .map(|isolation_level| format!("ISOLATION LEVEL {}", isolation_level.as_sql()));
+
+    if let Some(isolation_level) = &isolation_level {
+      modes.push(isolation_level);
+    }
+
+    match self.read_only {
+      Some(true) => modes.push("READ ONLY"),
+      Some(false) => modes.push("READ WRITE"),
+      None => (),
+    }
+
+    match self.deferrable {
+      Some(true) => modes.push("DEFERRABLE"),
+      Some(false) => modes.push("NOT DEFERRABLE"),
+      None => (),
+    }
+
+    match modes.is_empty() {
+      true => "BEGIN".to_string(),
+      false => format!("BEGIN {}", modes.join(", ")),
+    }
+  }
+}
+
+/* ========================================================================== *
+ * TRANSACTION                                                                *
+ * ========================================================================== */
+
+/// A guard for a transaction (or a savepoint nested within it).
+///
+/// Transactions must be explicitly [committed][PQTransaction::commit], and
+/// will be automatically rolled back when dropped otherwise.
+///
+pub struct PQTransaction<'a> {
+  id: usize,
+  connection: &'a PQConnection,
+  savepoint: Option<String>,
+  savepoints: Arc<AtomicUsize>,
+  depth: usize,
+  finished: bool,
+}
+
+debug_self!(PQTransaction<'_>, id);
+
+impl Drop for PQTransaction<'_> {
+  /// Roll back the transaction (or savepoint) unless it was already committed
+  /// or rolled back.
+  ///
+  fn drop(&mut self) {
+    debug_drop!(self);
+    if self.finished { return }
+
+    if let Err(error) = self.finish_rollback() {
+      debug!("Error rolling back {:?}: {}", self, error);
+    }
+  }
+}
+
+impl <'a> PQTransaction<'a> {
+  /// Start a new transaction on the specified [`PQConnection`].
+  ///
+  /// The connection must be idle (that is, not already in a transaction).
+  ///
+  /// See [`BEGIN`](https://www.postgresql.org/docs/current/sql-begin.html)
+  ///
+  pub fn begin(connection: &'a PQConnection, options: PQTransactionOptions) -> PQResult<Self> {
+    match connection.pq_transaction_status() {
+      PQTransactionStatus::Idle => (),
+      status => return Err(format!("Unable to begin transaction (status={:?})", status).into()),
+    }
+
+    execute(connection, options.to_begin_command())?;
+
+    Ok(debug_create!(Self {
+      id: debug_id(),
+      connection,
+      savepoint: None,
+      savepoints: Arc::new(AtomicUsize::new(0)),
+      depth: 0,
+      finished: false,
+    }))
+  }
+
+  /// Return the [`PQConnection`] this transaction is running on.
+  ///
+  pub fn connection(&self) -> &'a PQConnection {
+    self.connection
+  }
+
+  /// Return the nesting depth of this transaction (`0` for the outermost
+  /// transaction, `1` for its first savepoint, and so on...).
+  ///
+  pub fn depth(&self) -> usize {
+    self.depth
+  }
+
+  /// Create a new savepoint nested within this transaction.
+  ///
+  /// See [`SAVEPOINT`](https://www.postgresql.org/docs/current/sql-savepoint.html)
+  ///
+  pub fn savepoint(&self) -> PQResult<PQTransaction<'_>> {
+    self.verify()?;
+
+    // Names are unique within the whole transaction, never reused by siblings
+    let depth = self.depth + 1;
+    let sequence = self.savepoints.fetch_add(1, Ordering::Relaxed) + 1;
+    let savepoint = format!("pq_savepoint_{}_{}", depth, sequence);
+    execute(self.connection, format!("SAVEPOINT {}", savepoint))?;
+
+    Ok(debug_create!(Self {
+      id: debug_id(),
+      connection: self.connection,
+      savepoint: Some(savepoint),
+      savepoints: self.savepoints.clone(),
+      depth,
+      finished: false,
+    }))
+  }
+
+  /// Declare a new server-side [`PQCursor`] for the specified query within
+  /// this transaction.
+  ///
+  /// The cursor borrows this transaction, and therefore can not be used (or
+  /// closed) after the transaction ends.
+  ///
+  /// See [`DECLARE`](https://www.postgresql.org/docs/current/sql-declare.html)
+  ///
+  pub fn cursor(&self, query: &str, options: PQCursorOptions) -> PQResult<PQCursor<'_>> {
+    self.verify()?;
+    PQCursor::declare(self.connection, query, options)
+  }
+
+  /// Opens the large object with the specified OID within this transaction.
+  ///
+  /// The large object borrows this transaction, as its descriptor is closed
+  /// by the server when the transaction ends.
+  ///
+  /// See [`PQLargeObject`]
+  ///
+  pub fn lo_open(&self, oid: u32, mode: PQLargeObjectMode) -> PQResult<PQLargeObject<'_>> {
+    self.verify()?;
+    PQLargeObject::open(self.connection, oid, mode)
+  }
+
+  /// Commit this transaction, or release this savepoint.
+  ///
+  /// If the transaction is in a failed state (for example, because one of its
+  /// statements failed) the transaction is rolled back and an error returned.
+  ///
+  /// See [`COMMIT`](https://www.postgresql.org/docs/current/sql-commit.html)
+  /// See [`RELEASE SAVEPOINT`](https://www.postgresql.org/docs/current/sql-release-savepoint.html)
+  ///
+  pub fn commit(mut self) -> PQResult<()> {
+    self.finished = true;
+
+    if let Err(error) = self.verify() {
+      self.finish_rollback()?;
+      return Err(error);
+    }
+
+    let response = match &self.savepoint {
+      Some(savepoint) => execute(self.connection, format!("RELEASE SAVEPOINT {}", savepoint))?,
+      None => execute(self.connection, "COMMIT".to_string())?,
+    };
+
+    // PostgreSQL turns a "COMMIT" into a "ROLLBACK" for failed transactions
+    match response.pq_cmd_status().as_str() {
+      "ROLLBACK" => Err("Transaction was rolled back by the server".into()),
+      _ => Ok(()),
+    }
+  }
+
+  /// Roll back this transaction, or roll back to this savepoint.
+  ///
+  /// See [`ROLLBACK`](https://www.postgresql.org/docs/current/sql-rollback.html)
+  /// See [`ROLLBACK TO SAVEPOINT`](https://www.postgresql.org/docs/current/sql-rollback-to.html)
+  ///
+  pub fn rollback(mut self) -> PQResult<()> {
+    self.finished = true;
+    self.finish_rollback()
+  }
+
+  /// Prepare this transaction for two-phase commit, under the specified
+  /// global transaction identifier.
+  ///
+  /// Once prepared, the transaction is no longer associated with the current
+  /// session, and must be finished with [`PQConnection::commit_prepared`] or
+  /// [`PQConnection::rollback_prepared`] (possibly from another session).
+  ///
+  /// See [`PREPARE TRANSACTION`](https://www.postgresql.org/docs/current/sql-prepare-transaction.html)
+  ///
+  pub fn prepare(mut self, gid: &PQGlobalTransactionId) -> PQResult<()> {
+    if self.savepoint.is_some() {
+      return Err("Unable to prepare a savepoint for two-phase commit".into())
+    }
+
+    self.finished = true;
+
+    if let Err(error) = self.verify() {
+      self.finish_rollback()?;
+      return Err(error);
+    }
+
+    let command = format!("PREPARE TRANSACTION {}", gid.to_sql_literal());
+    let response = execute(self.connection, command)?;
+
+    // Same as "COMMIT", a failed transaction is simply rolled back
+    match response.pq_cmd_status().as_str() {
+      "ROLLBACK" => Err("Transaction was rolled back by the server".into()),
+      _ => Ok(()),
+    }
+  }
+
+  /// Verify that the transaction is still usable, that is the server is not
+  /// reporting it as _failed_ (`PQTRANS_INERROR`).
+  ///
+  pub fn verify(&self) -> PQResult<()> {
+    match self.connection.pq_transaction_status() {
+      PQTransactionStatus::InTransaction => Ok(()),
+      PQTransactionStatus::InError => Err("Transaction is in a failed state".into()),
+      status => Err(format!("Connection is not in a transaction (status={:?})", status).into()),
+    }
+  }
+
+  /// Roll back this transaction or savepoint, without consuming it.
+  ///
+  fn finish_rollback(&mut self) -> PQResult<()> {
+    self.finished = true;
+
+    match &self.savepoint {
+      Some(savepoint) => {
+        execute(self.connection, format!("ROLLBACK TO SAVEPOINT {}", savepoint))?;
+        execute(self.connection, format!("RELEASE SAVEPOINT {}", savepoint))?;
+      },
+      None => {
+        execute(self.connection, "ROLLBACK".to_string())?;
+      },
+    }
+
+    Ok(())
+  }
+}
+
+/// Execute a command, returning an error if it failed.
+///
+fn execute(connection: &PQConnection, command: String) -> PQResult<PQResponse> {
+  debug!("Executing transaction command \"{}\"", command);
+  connection.pq_exec(command)?.into_result()
+}
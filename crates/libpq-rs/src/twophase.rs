@@ -0,0 +1,242 @@
+//! Two-phase commit: prepared transactions and their recovery.
+
+use crate::connection::PQConnection;
+use crate::debug::*;
+use crate::errors::*;
+use std::fmt::Display;
+use std::time::Duration;
+
+/// The maximum length (in bytes) of a global transaction identifier.
+static GID_MAX_LENGTH: usize = 199;
+
+/* ========================================================================== *
+ * GLOBAL TRANSACTION ID                                                      *
+ * ========================================================================== */
+
+/// A global transaction identifier, as used by `PREPARE TRANSACTION`.
+///
+/// Identifiers must be non-empty, shorter than 200 bytes, and must not
+/// contain any `NUL` character.
+///
+/// See [`PREPARE TRANSACTION`](https://www.postgresql.org/docs/current/sql-prepare-transaction.html)
+///
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PQGlobalTransactionId {
+  gid: String,
+}
+
+impl TryFrom<&str> for PQGlobalTransactionId {
+  type Error = PQError;
+
+  /// Create a [`PQGlobalTransactionId`] validating its contents.
+  ///
+  fn try_from(gid: &str) -> PQResult<Self> {
+    if gid.is_empty() {
+      Err("Global transaction identifier must not be empty".into())
+    } else if gid.len() > GID_MAX_LENGTH {
+      Err(format!("Global transaction identifier too long ({} > {} bytes)", gid.len(), GID_MAX_LENGTH).into())
+    } else if gid.contains('\0') {
+      Err("Global transaction identifier must not contain NUL characters".into())
+    } else {
+      Ok(Self { gid: gid.to_string() })
+    }
+  }
+}
+
+impl TryFrom<String> for PQGlobalTransactionId {
+  type Error = PQError;
+
+  /// Create a [`PQGlobalTransactionId`] validating its contents.
+  ///
+  fn try_from(gid: String) -> PQResult<Self> {
+    Self::try_from(gid.as_str())
+  }
+}
+
+impl Display for PQGlobalTransactionId {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(&self.gid)
+  }
+}
+
+impl PQGlobalTransactionId {
+  /// Return this identifier as a borrowed [`str`]_ing_.
+  ///
+  pub fn as_str(&self) -> &str {
+    &self.gid
+  }
+
+  /// Return this identifier as a quoted SQL string literal.
+  ///
+  /// Backslashes trigger the `E'...'` syntax, so that the literal is valid
+  /// regardless of the server's `standard_conforming_strings` setting.
+  ///
+  pub fn to_sql_literal(&self) -> String {
+    let quoted = self.gid.replace('\'', "''");
+    match quoted.contains('\\') {
+      true => format!("E'{}'", quoted.replace('\\', "\\\\")),
+      false => format!("'{}'", quoted),
+    }
+  }
+}
+
+/* ========================================================================== *
+ * PREPARED TRANSACTIONS                                                      *
+ * ========================================================================== */
+
+/// A transaction prepared for two-phase commit, and awaiting resolution.
+///
+/// See [`pg_prepared_xacts`](https://www.postgresql.org/docs/current/view-pg-prepared-xacts.html)
+///
+#[derive(Debug, Clone)]
+pub struct PQPreparedTransaction {
+  /// The numeric transaction identifier of the prepared transaction.
+  pub transaction: String,
+  /// The global transaction identifier assigned to the transaction.
+  pub gid: PQGlobalTransactionId,
+  /// The time at which the transaction was prepared for commit.
+  pub prepared: String,
+  /// How long ago the transaction was prepared for commit.
+  pub age: Duration,
+  /// The name of the user that executed the transaction.
+  pub owner: String,
+  /// The name of the database in which the transaction was executed.
+  pub database: String,
+}
+
+/// What to do with a [`PQPreparedTransaction`] during recovery.
+///
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub enum PQPreparedDecision {
+  /// Commit the prepared transaction (`COMMIT PREPARED`).
+  Commit = 0,
+  /// Roll back the prepared transaction (`ROLLBACK PREPARED`).
+  Rollback = 1,
+  /// Leave the prepared transaction alone.
+  Ignore = 2,
+}
+
+/// Commit a transaction previously prepared for two-phase commit.
+///
+/// See [`COMMIT PREPARED`](https://www.postgresql.org/docs/current/sql-commit-prepared.html)
+///
+pub fn commit_prepared(connection: &PQConnection, gid: &PQGlobalTransactionId) -> PQResult<()> {
+  let command = format!("COMMIT PREPARED {}", gid.to_sql_literal());
+  connection.pq_exec(command)?.into_result().map(|_| ())
+}
+
+/// Roll back a transaction previously prepared for two-phase commit.
+///
+/// See [`ROLLBACK PREPARED`](https://www.postgresql.org/docs/current/sql-rollback-prepared.html)
+///
+pub fn rollback_prepared(connection: &PQConnection, gid: &PQGlobalTransactionId) -> PQResult<()> {
+  let command = format!("ROLLBACK PREPARED {}", gid.to_sql_literal());
+  connection.pq_exec(command)?.into_result().map(|_| ())
+}
+
+/// List all transactions prepared in the _current_ database (the only ones
+/// which can be committed or rolled back from this connection), oldest first.
+///
+/// See [`pg_prepared_xacts`](https://www.postgresql.org/docs/current/view-pg-prepared-xacts.html)
+///
+pub fn prepared_transactions(connection: &PQConnection) -> PQResult<Vec<PQPreparedTransaction>> {
+  let response = connection.pq_exec("\
+    SELECT transaction::text, gid, prepared::text, \
+           extract(epoch FROM now() - prepared)::float8, owner, database \
+      FROM pg_catalog.pg_prepared_xacts \
+     WHERE database = pg_catalog.current_database() \
+     ORDER BY prepared".to_string())?.into_result()?;
+
+  let mut transactions = Vec::<PQPreparedTransaction>::new();
+
+  for row in 0 .. response.pq_ntuples() {
+    let value = |column: i32| -> PQResult<String> {
+      response.pq_getvalue(row, column)?
+        .ok_or_else(|| "Unexpected null value in \"pg_prepared_xacts\"".into())
+    };
+
+    let age = value(3)?.parse::<f64>()
+      .map_err(|err| format!("Invalid age for prepared transaction: {}", err))?;
+
+    transactions.push(PQPreparedTransaction {
+      transaction: value(0)?,
+      gid: PQGlobalTransactionId::try_from(value(1)?)?,
+      prepared: value(2)?,
+      age: Duration::from_secs_f64(age.max(0.0)),
+      owner: value(4)?,
+      database: value(5)?,
+    });
+  }
+
+  Ok(transactions)
+}
+
+/// Resolve all transactions prepared in the current database, committing or
+/// rolling back each one according to the decision function specified.
+///
+/// This returns all prepared transactions found, alongside the decision that
+/// was taken for each one of them and the outcome of applying it: a failure
+/// resolving one transaction does not prevent the others from being resolved.
+///
+pub fn recover_prepared_transactions<F>(
+  connection: &PQConnection,
+  mut decide: F,
+) -> PQResult<Vec<(PQPreparedTransaction, PQPreparedDecision, PQResult<()>)>>
+where
+  F: FnMut(&PQPreparedTransaction) -> PQPreparedDecision,
+{
+  let mut resolved = Vec::<(PQPreparedTransaction, PQPreparedDecision, PQResult<()>)>::new();
+
+  for transaction in prepared_transactions(connection)? {
+    let decision = decide(&transaction);
+    debug!("Recovering prepared transaction \"{}\": {:?}", transaction.gid, decision);
+
+    let result = match decision {
+      PQPreparedDecision::Commit => commit_prepared(connection, &transaction.gid),
+      PQPreparedDecision::Rollback => rollback_prepared(connection, &transaction.gid),
+      PQPreparedDecision::Ignore => Ok(()),
+    };
+
+    if let Err(error) = &result {
+      debug!("Error recovering prepared transaction \"{}\": {}", transaction.gid, error);
+    }
+
+    resolved.push((transaction, decision, result));
+  }
+
+  Ok(resolved)
+}
+
+/* ========================================================================== *
+ * TESTS                                                                      *
+ * ========================================================================== */
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn gid_validation() {
+    assert!(PQGlobalTransactionId::try_from("").is_err());
+    assert!(PQGlobalTransactionId::try_from("a\0b").is_err());
+
+    // The limit is in bytes, not characters
+    let longest = "x".repeat(GID_MAX_LENGTH);
+    assert_eq!(PQGlobalTransactionId::try_from(longest.as_str()).unwrap().as_str(), longest);
+    assert!(PQGlobalTransactionId::try_from("x".repeat(GID_MAX_LENGTH + 1)).is_err());
+    assert!(PQGlobalTransactionId::try_from("é".repeat(99)).is_ok());
+    assert!(PQGlobalTransactionId::try_from("é".repeat(100)).is_err());
+  }
+
+  #[test]
+  fn gid_sql_literals() {
+    let literal = | gid: &str | PQGlobalTransactionId::try_from(gid).unwrap().to_sql_literal();
+
+    assert_eq!(literal("gid-1"), "'gid-1'");
+    assert_eq!(literal("it's"), "'it''s'");
+    assert_eq!(literal("''"), "''''''");
+    assert_eq!(literal("a\\b"), "E'a\\\\b'");
+    assert_eq!(literal("it's a\\b"), "E'it''s a\\\\b'");
+  }
+}